@@ -0,0 +1,359 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error::AppError;
+use crate::{CreateGameRequest, GuessSettings};
+
+// 未指定配置文件时，当前目录下存在该文件就读取它
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+// 玩家按键只定义了4个（空格、回车、A、L）
+pub const MAX_SUPPORTED_PLAYERS: u8 = 4;
+
+const USAGE: &str = "用法: guess_number [选项]
+
+选项:
+  --config <路径>         配置文件（TOML），也可用 GAME_CONFIG 环境变量指定
+  --bind <地址>           监听地址，如 0.0.0.0:8082（BIND_ADDRESS）
+  --workers <数量>        工作线程数量（WORKER_THREADS）
+  --database <路径>       SQLite 文件路径，memory 表示内存数据库（GAME_DATABASE）
+  --static-dir <目录>     从磁盘目录提供静态文件（STATIC_DIR）
+  --max-players <数量>    每局最多玩家数（MAX_PLAYERS）
+  --game-timeout <秒>     游戏空闲超时（GAME_TIMEOUT）
+  --max-games <数量>      同时进行的游戏数量上限（MAX_GAMES）
+  --snapshot <路径>       赛车游戏快照文件，off 表示不保存（GAME_SNAPSHOT）
+  -h, --help              显示本帮助
+
+优先级：命令行参数 > 环境变量 > 配置文件 > 默认值";
+
+// 服务器配置，各项的默认值与之前写死在代码里的一致
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub guess: GuessConfig,
+    pub racing: RacingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub worker_threads: usize,
+    pub database: String,              // SQLite 文件路径，memory 表示内存数据库
+    pub static_dir: Option<PathBuf>,   // 设置后总是从该目录读取静态文件
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:8082".to_string(),
+            worker_threads: 8,
+            database: "game_records.db".to_string(),
+            static_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GuessConfig {
+    pub min: u32,
+    pub max: u32,
+    pub max_attempts: u32,
+    pub session_timeout_secs: u64, // 会话空闲超过该时间后被清理
+    pub max_sessions: usize,
+}
+
+impl Default for GuessConfig {
+    fn default() -> Self {
+        Self {
+            min: 1,
+            max: 100,
+            max_attempts: 10,
+            session_timeout_secs: 1800,
+            max_sessions: 10000,
+        }
+    }
+}
+
+impl GuessConfig {
+    // normal 难度的范围和次数
+    pub fn settings(&self) -> GuessSettings {
+        GuessSettings {
+            min: self.min,
+            max: self.max,
+            max_attempts: self.max_attempts,
+        }
+    }
+
+    pub fn session_timeout(&self) -> Duration {
+        Duration::from_secs(self.session_timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RacingConfig {
+    pub min_players: u8,
+    pub max_players: u8,
+    pub min_rounds: u8,
+    pub max_rounds: u8,
+    pub max_name_length: usize,        // 玩家名称最多字符数
+    pub game_timeout_secs: u64,        // 游戏没有玩家操作超过该时间后被清理
+    pub max_games: usize,              // 同时进行的游戏数量上限
+    pub max_connections: usize,        // 同时保持的 WebSocket 连接数量上限，每个连接占用一个线程
+    pub snapshot_file: String,         // 游戏快照文件，off 表示不保存
+    pub snapshot_interval_secs: u64,
+    pub false_start_threshold_ms: f64, // 绿灯后小于该时间视为抢跑
+    pub clock_tolerance_ms: f64,       // 按键时间戳在单程延迟之外允许提前的抖动余量
+    pub default_game: CreateGameRequest, // 创建游戏的请求体为空时使用
+}
+
+impl Default for RacingConfig {
+    fn default() -> Self {
+        Self {
+            min_players: 1,
+            max_players: MAX_SUPPORTED_PLAYERS,
+            min_rounds: 3,
+            max_rounds: 10,
+            max_name_length: 20,
+            game_timeout_secs: 300,
+            max_games: 1000,
+            max_connections: 256,
+            snapshot_file: "racing_games.json".to_string(),
+            snapshot_interval_secs: 30,
+            false_start_threshold_ms: 100.0,
+            clock_tolerance_ms: 30.0,
+            default_game: CreateGameRequest::default(),
+        }
+    }
+}
+
+impl RacingConfig {
+    pub fn timing(&self) -> RaceTiming {
+        RaceTiming {
+            false_start_threshold_ms: self.false_start_threshold_ms,
+            clock_tolerance_ms: self.clock_tolerance_ms,
+        }
+    }
+
+    pub fn game_timeout(&self) -> Duration {
+        Duration::from_secs(self.game_timeout_secs)
+    }
+
+    pub fn snapshot_path(&self) -> Option<PathBuf> {
+        (!self.snapshot_file.eq_ignore_ascii_case("off")).then(|| PathBuf::from(&self.snapshot_file))
+    }
+
+    pub fn snapshot_interval(&self) -> Duration {
+        Duration::from_secs(self.snapshot_interval_secs)
+    }
+}
+
+// 每局游戏创建时确定的计时规则，之后修改配置不影响进行中的游戏
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct RaceTiming {
+    pub false_start_threshold_ms: f64,
+    pub clock_tolerance_ms: f64,
+}
+
+impl Default for RaceTiming {
+    fn default() -> Self {
+        RacingConfig::default().timing()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: PathBuf, error: String },
+    Parse { path: PathBuf, error: String },
+    Argument(String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => write!(f, "无法读取配置文件 {}: {}", path.display(), error),
+            ConfigError::Parse { path, error } => write!(f, "配置文件 {} 格式错误: {}", path.display(), error),
+            ConfigError::Argument(message) => write!(f, "{}\n\n{}", message, USAGE),
+            ConfigError::Invalid(problems) => write!(f, "配置无效:\n  - {}", problems.join("\n  - ")),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // 依次应用默认值、配置文件、环境变量和命令行参数，最后统一校验
+    pub fn load() -> Result<Self, ConfigError> {
+        let args = parse_args(std::env::args().skip(1))?;
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        let explicit = args
+            .iter()
+            .find(|(flag, _)| flag == "config")
+            .map(|(_, value)| PathBuf::from(value))
+            .or_else(|| env("GAME_CONFIG").map(PathBuf::from));
+        let mut config = match explicit {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Self::default(),
+        };
+
+        for key in ["BIND_ADDRESS", "WORKER_THREADS", "GAME_DATABASE", "STATIC_DIR", "MAX_PLAYERS", "GAME_TIMEOUT", "MAX_GAMES", "GAME_SNAPSHOT"] {
+            if let Some(value) = env(key) {
+                config.apply(key, &value)?;
+            }
+        }
+        for (flag, value) in &args {
+            if flag != "config" {
+                config.apply(flag, value)?;
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigError::Read {
+            path: path.to_path_buf(),
+            error: e.to_string(),
+        })?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse {
+            path: path.to_path_buf(),
+            error: e.to_string(),
+        })
+    }
+
+    // 环境变量和命令行参数共用的覆盖项，key 为环境变量名或去掉 -- 的参数名
+    fn apply(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "BIND_ADDRESS" | "bind" => self.server.bind = value.to_string(),
+            "WORKER_THREADS" | "workers" => self.server.worker_threads = parse_value(key, value)?,
+            "GAME_DATABASE" | "database" => self.server.database = value.to_string(),
+            "STATIC_DIR" | "static-dir" => self.server.static_dir = Some(PathBuf::from(value)),
+            "MAX_PLAYERS" | "max-players" => self.racing.max_players = parse_value(key, value)?,
+            "GAME_TIMEOUT" | "game-timeout" => self.racing.game_timeout_secs = parse_value(key, value)?,
+            "MAX_GAMES" | "max-games" => self.racing.max_games = parse_value(key, value)?,
+            "GAME_SNAPSHOT" | "snapshot" => self.racing.snapshot_file = value.to_string(),
+            _ => return Err(ConfigError::Argument(format!("未知参数: --{}", key))),
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let server = &self.server;
+        let guess = &self.guess;
+        let racing = &self.racing;
+
+        if server.bind.to_socket_addrs().map(|mut addrs| addrs.next().is_none()).unwrap_or(true) {
+            problems.push(format!("server.bind 不是有效的监听地址: {}", server.bind));
+        }
+        if server.worker_threads == 0 {
+            problems.push("server.worker_threads 至少为 1".to_string());
+        }
+        if server.database.trim().is_empty() {
+            problems.push("server.database 不能为空".to_string());
+        }
+
+        if guess.min >= guess.max {
+            problems.push(format!("guess.min ({}) 必须小于 guess.max ({})", guess.min, guess.max));
+        }
+        if guess.max_attempts == 0 {
+            problems.push("guess.max_attempts 至少为 1".to_string());
+        }
+        if guess.session_timeout_secs == 0 {
+            problems.push("guess.session_timeout_secs 至少为 1".to_string());
+        }
+        if guess.max_sessions == 0 {
+            problems.push("guess.max_sessions 至少为 1".to_string());
+        }
+
+        if !(1..=MAX_SUPPORTED_PLAYERS).contains(&racing.max_players) {
+            problems.push(format!("racing.max_players 须在 1 到 {} 之间", MAX_SUPPORTED_PLAYERS));
+        }
+        if racing.min_players == 0 || racing.min_players > racing.max_players {
+            problems.push("racing.min_players 须在 1 到 racing.max_players 之间".to_string());
+        }
+        if racing.min_rounds == 0 || racing.min_rounds > racing.max_rounds {
+            problems.push("racing.min_rounds 须在 1 到 racing.max_rounds 之间".to_string());
+        }
+        if racing.max_name_length == 0 {
+            problems.push("racing.max_name_length 至少为 1".to_string());
+        }
+        if racing.game_timeout_secs == 0 {
+            problems.push("racing.game_timeout_secs 至少为 1".to_string());
+        }
+        if racing.max_games == 0 {
+            problems.push("racing.max_games 至少为 1".to_string());
+        }
+        if racing.max_connections == 0 {
+            problems.push("racing.max_connections 至少为 1".to_string());
+        }
+        if racing.snapshot_file.trim().is_empty() {
+            problems.push("racing.snapshot_file 不能为空，不需要快照时设为 off".to_string());
+        }
+        if racing.snapshot_interval_secs == 0 {
+            problems.push("racing.snapshot_interval_secs 至少为 1".to_string());
+        }
+        if !racing.false_start_threshold_ms.is_finite() || racing.false_start_threshold_ms < 0.0 {
+            problems.push("racing.false_start_threshold_ms 不能为负数".to_string());
+        }
+        if !racing.clock_tolerance_ms.is_finite() || racing.clock_tolerance_ms < 0.0 {
+            problems.push("racing.clock_tolerance_ms 不能为负数".to_string());
+        }
+        // 默认游戏也要满足上面的人数、回合数和名称限制
+        if let Err(AppError::Validation(errors)) = racing.default_game.validate(racing) {
+            problems.extend(
+                errors
+                    .into_iter()
+                    .map(|error| format!("racing.default_game.{} {}", error.field, error.message)),
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ConfigError::Argument(format!("{} 的值无法解析: {}", key, value)))
+}
+
+// 解析 --name value 和 --name=value 形式的参数
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut parsed = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            return Err(ConfigError::Argument(format!("无法识别的参数: {}", arg)));
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| ConfigError::Argument(format!("参数 --{} 缺少值", flag)))?;
+                (flag.to_string(), value)
+            }
+        };
+        parsed.push((name, value));
+    }
+    Ok(parsed)
+}
@@ -1,24 +1,198 @@
+use chrono::{DateTime, Datelike, Duration, SecondsFormat, Utc};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
+use crate::error::AppError;
+use crate::{lock_or_recover, Difficulty};
+
+// 游戏类型
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameType {
+    Racing,
+    GuessNumber,
+}
+
+impl GameType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameType::Racing => "racing",
+            GameType::GuessNumber => "guess_number",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "racing" => Some(GameType::Racing),
+            "guess_number" => Some(GameType::GuessNumber),
+            _ => None,
+        }
+    }
+}
+
 // 游戏记录结构体
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameRecord {
     pub id: String,
+    pub game_type: GameType,
     pub game_id: String,
     pub player_name: String,
     pub score: i32,
     pub reaction_time: Option<f64>,
-    pub created_at: String,
+    pub attempts: Option<u32>,
+    pub duration_ms: Option<u64>,
+    pub difficulty: Option<Difficulty>, // 猜数字游戏的难度
+    pub created_at: DateTime<Utc>,
+}
+
+// 待保存的游戏记录，ID和时间戳由数据库生成
+#[derive(Debug, Clone)]
+pub struct NewGameRecord {
+    pub game_type: GameType,
+    pub game_id: String,
+    pub player_name: String,
+    pub score: i32,
+    pub reaction_time: Option<f64>,
+    pub attempts: Option<u32>,
+    pub duration_ms: Option<u64>,
+    pub difficulty: Option<Difficulty>,
+}
+
+impl NewGameRecord {
+    fn into_record(self) -> GameRecord {
+        GameRecord {
+            id: new_record_id(),
+            game_type: self.game_type,
+            game_id: self.game_id,
+            player_name: self.player_name,
+            score: self.score,
+            reaction_time: self.reaction_time,
+            attempts: self.attempts,
+            duration_ms: self.duration_ms,
+            difficulty: self.difficulty,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+// 生成唯一的记录ID
+fn new_record_id() -> String {
+    format!("record_{}", uuid::Uuid::new_v4().simple())
+}
+
+// 统一的时间戳存储格式（毫秒精度，UTC），字符串顺序即时间顺序
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// 排行榜时间范围（按UTC自然日/自然周计算）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeWindow {
+    Daily,
+    Weekly,
+    AllTime,
+}
+
+impl TimeWindow {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "daily" => Some(TimeWindow::Daily),
+            "weekly" => Some(TimeWindow::Weekly),
+            "all" | "all_time" => Some(TimeWindow::AllTime),
+            _ => None,
+        }
+    }
+
+    // 时间范围的起点，AllTime 没有起点
+    pub fn since(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = now.date_naive().and_hms_opt(0, 0, 0)?.and_utc();
+        match self {
+            TimeWindow::Daily => Some(today),
+            TimeWindow::Weekly => Some(today - Duration::days(now.weekday().num_days_from_monday() as i64)),
+            TimeWindow::AllTime => None,
+        }
+    }
+}
+
+// 排行榜排序方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderboardSort {
+    Score,        // 积分从高到低
+    ReactionTime, // 反应时间从快到慢，忽略没有反应时间的记录
+}
+
+impl LeaderboardSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "score" => Some(LeaderboardSort::Score),
+            "reaction_time" => Some(LeaderboardSort::ReactionTime),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, a: &GameRecord, b: &GameRecord) -> std::cmp::Ordering {
+        match self {
+            // 同分时尝试次数少、用时短、记录早者在前
+            LeaderboardSort::Score => b
+                .score
+                .cmp(&a.score)
+                .then(a.attempts.cmp(&b.attempts))
+                .then(a.duration_ms.cmp(&b.duration_ms))
+                .then(a.created_at.cmp(&b.created_at)),
+            LeaderboardSort::ReactionTime => a
+                .reaction_time
+                .unwrap_or(f64::INFINITY)
+                .total_cmp(&b.reaction_time.unwrap_or(f64::INFINITY))
+                .then(a.created_at.cmp(&b.created_at)),
+        }
+    }
+
+    fn order_by(&self) -> &'static str {
+        match self {
+            LeaderboardSort::Score => "score DESC, attempts ASC, duration_ms ASC, created_at ASC, id ASC",
+            LeaderboardSort::ReactionTime => "reaction_time ASC, created_at ASC, id ASC",
+        }
+    }
+}
+
+// 排行榜查询条件
+#[derive(Debug, Clone)]
+pub struct LeaderboardQuery {
+    pub game_type: Option<GameType>,
+    pub difficulty: Option<Difficulty>,
+    pub window: TimeWindow,
+    pub sort: LeaderboardSort,
+    pub unique_players: bool, // 每位玩家只保留最好的一条
+    pub limit: i64,
+}
+
+impl Default for LeaderboardQuery {
+    fn default() -> Self {
+        Self {
+            game_type: None,
+            difficulty: None,
+            window: TimeWindow::AllTime,
+            sort: LeaderboardSort::Score,
+            unique_players: false,
+            limit: 10,
+        }
+    }
 }
 
 // 游戏数据库 trait
 pub trait GameDatabase {
-    fn save_game_record(&self, game_id: &str, player_name: &str, score: i32, reaction_time: Option<f64>) -> Result<(), String>;
-    fn get_leaderboard(&self, limit: i64) -> Result<Vec<GameRecord>, String>;
-    fn get_player_history(&self, player_name: &str, limit: i64) -> Result<Vec<GameRecord>, String>;
-    fn get_stats(&self) -> Result<(i64, i64), String>;
+    fn save_game_record(&self, record: NewGameRecord) -> Result<GameRecord, AppError>;
+    fn get_leaderboard(&self, query: &LeaderboardQuery) -> Result<Vec<GameRecord>, AppError>;
+    fn get_player_history(&self, player_name: &str, since: Option<DateTime<Utc>>, limit: i64) -> Result<Vec<GameRecord>, AppError>;
+    fn get_stats(&self) -> Result<(i64, i64), AppError>;
     fn is_connected(&self) -> bool;
+    // 关闭前调用，确保已提交的记录写入磁盘
+    fn flush(&self) -> Result<(), AppError> {
+        Ok(())
+    }
 }
 
 // 内存数据库实现
@@ -35,46 +209,51 @@ impl InMemoryDatabase {
 }
 
 impl GameDatabase for InMemoryDatabase {
-    fn save_game_record(&self, game_id: &str, player_name: &str, score: i32, reaction_time: Option<f64>) -> Result<(), String> {
-        let mut records = self.records.lock().map_err(|e| e.to_string())?;
-        
-        let record = GameRecord {
-            id: format!("record_{}", records.len() + 1),
-            game_id: game_id.to_string(),
-            player_name: player_name.to_string(),
-            score,
-            reaction_time,
-            created_at: "2024-01-01T00:00:00Z".to_string(),
-        };
+    fn save_game_record(&self, record: NewGameRecord) -> Result<GameRecord, AppError> {
+        let mut records = lock_or_recover(&self.records);
         
-        records.push(record);
-        Ok(())
+        let record = record.into_record();
+        records.push(record.clone());
+        Ok(record)
     }
 
-    fn get_leaderboard(&self, limit: i64) -> Result<Vec<GameRecord>, String> {
-        let records = self.records.lock().map_err(|e| e.to_string())?;
-        let mut sorted_records = records.clone();
-        
-        sorted_records.sort_by(|a, b| b.score.cmp(&a.score));
-        sorted_records.truncate(limit as usize);
+    fn get_leaderboard(&self, query: &LeaderboardQuery) -> Result<Vec<GameRecord>, AppError> {
+        let records = lock_or_recover(&self.records);
+        let since = query.window.since(Utc::now());
+        let mut sorted_records: Vec<GameRecord> = records
+            .iter()
+            .filter(|r| query.game_type.is_none_or(|game_type| r.game_type == game_type))
+            .filter(|r| query.difficulty.is_none_or(|difficulty| r.difficulty == Some(difficulty)))
+            .filter(|r| since.is_none_or(|since| r.created_at >= since))
+            .filter(|r| query.sort != LeaderboardSort::ReactionTime || r.reaction_time.is_some())
+            .cloned()
+            .collect();
+
+        sorted_records.sort_by(|a, b| query.sort.compare(a, b));
+        if query.unique_players {
+            let mut seen = std::collections::HashSet::new();
+            sorted_records.retain(|r| seen.insert(r.player_name.clone()));
+        }
+        sorted_records.truncate(query.limit.max(0) as usize);
         Ok(sorted_records)
     }
 
-    fn get_player_history(&self, player_name: &str, limit: i64) -> Result<Vec<GameRecord>, String> {
-        let records = self.records.lock().map_err(|e| e.to_string())?;
+    fn get_player_history(&self, player_name: &str, since: Option<DateTime<Utc>>, limit: i64) -> Result<Vec<GameRecord>, AppError> {
+        let records = lock_or_recover(&self.records);
         let mut player_records: Vec<GameRecord> = records
             .iter()
             .filter(|r| r.player_name == player_name)
+            .filter(|r| since.is_none_or(|since| r.created_at >= since))
             .cloned()
             .collect();
         
-        player_records.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        player_records.sort_by_key(|r| std::cmp::Reverse(r.created_at));
         player_records.truncate(limit as usize);
         Ok(player_records)
     }
 
-    fn get_stats(&self) -> Result<(i64, i64), String> {
-        let records = self.records.lock().map_err(|e| e.to_string())?;
+    fn get_stats(&self) -> Result<(i64, i64), AppError> {
+        let records = lock_or_recover(&self.records);
         let total_games = records.len() as i64;
         let unique_players = records
             .iter()
@@ -90,37 +269,503 @@ impl GameDatabase for InMemoryDatabase {
     }
 }
 
+// SQLite 数据库迁移，按版本顺序执行，已执行的版本记录在 user_version 中
+const MIGRATIONS: &[&str] = &[
+    // v1: 游戏记录表
+    "CREATE TABLE game_records (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        game_id TEXT NOT NULL,
+        player_name TEXT NOT NULL,
+        score INTEGER NOT NULL,
+        reaction_time REAL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX idx_game_records_score ON game_records(score DESC);
+    CREATE INDEX idx_game_records_player ON game_records(player_name, created_at DESC);",
+    // v2: 全局唯一的记录ID，时间戳统一为毫秒精度的UTC格式
+    "ALTER TABLE game_records ADD COLUMN record_id TEXT;
+    UPDATE game_records SET record_id = 'record_' || id;
+    CREATE UNIQUE INDEX idx_game_records_record_id ON game_records(record_id);
+    UPDATE game_records SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', created_at);",
+    // v3: 区分游戏类型，记录猜数字游戏的尝试次数和用时
+    "ALTER TABLE game_records ADD COLUMN game_type TEXT NOT NULL DEFAULT 'racing';
+    ALTER TABLE game_records ADD COLUMN attempts INTEGER;
+    ALTER TABLE game_records ADD COLUMN duration_ms INTEGER;
+    CREATE INDEX idx_game_records_type_score ON game_records(game_type, score DESC);",
+    // v4: 反应时间排行榜
+    "CREATE INDEX idx_game_records_type_reaction ON game_records(game_type, reaction_time ASC);",
+    // v5: 猜数字游戏按难度分别计分，之前的记录都是默认难度
+    "ALTER TABLE game_records ADD COLUMN difficulty TEXT;
+    UPDATE game_records SET difficulty = 'normal' WHERE game_type = 'guess_number';
+    CREATE INDEX idx_game_records_type_difficulty_score ON game_records(game_type, difficulty, score DESC);",
+];
+
+const RECORD_COLUMNS: &str =
+    "record_id, game_type, game_id, player_name, score, reaction_time, attempts, duration_ms, created_at, difficulty";
+
+// SQLite 文件数据库实现
+pub struct SqliteDatabase {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        let conn = Connection::open(path)?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<Self, AppError> {
+        Self::migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn migrate(conn: &mut Connection) -> Result<(), AppError> {
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        if version > MIGRATIONS.len() {
+            return Err(AppError::Database(format!("数据库版本 {} 高于程序支持的版本 {}", version, MIGRATIONS.len())));
+        }
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration).map_err(|e| AppError::Database(format!("迁移 v{} 失败: {}", index + 1, e)))?;
+            tx.pragma_update(None, "user_version", index + 1)?;
+            tx.commit()?;
+            println!("🗄️ 数据库已迁移到 v{}", index + 1);
+        }
+        Ok(())
+    }
+
+    // 按 RECORD_COLUMNS 的列顺序读取一条记录
+    fn row_to_record(row: &rusqlite::Row) -> rusqlite::Result<GameRecord> {
+        let game_type: String = row.get(1)?;
+        let game_type = GameType::parse(&game_type).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(1, Type::Text, format!("未知的游戏类型: {}", game_type).into())
+        })?;
+        let created_at: String = row.get(8)?;
+        let created_at = DateTime::parse_from_rfc3339(&created_at)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(8, Type::Text, Box::new(e)))?
+            .with_timezone(&Utc);
+
+        let difficulty: Option<String> = row.get(9)?;
+        let difficulty = difficulty
+            .map(|difficulty| {
+                Difficulty::parse(&difficulty).ok_or_else(|| {
+                    rusqlite::Error::FromSqlConversionFailure(9, Type::Text, format!("未知的难度: {}", difficulty).into())
+                })
+            })
+            .transpose()?;
+
+        Ok(GameRecord {
+            id: row.get(0)?,
+            game_type,
+            game_id: row.get(2)?,
+            player_name: row.get(3)?,
+            score: row.get(4)?,
+            reaction_time: row.get(5)?,
+            attempts: row.get(6)?,
+            duration_ms: row.get(7)?,
+            difficulty,
+            created_at,
+        })
+    }
+}
+
+impl GameDatabase for SqliteDatabase {
+    fn save_game_record(&self, record: NewGameRecord) -> Result<GameRecord, AppError> {
+        let conn = lock_or_recover(&self.conn);
+        let record = record.into_record();
+        conn.execute(
+            &format!("INSERT INTO game_records ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", RECORD_COLUMNS),
+            params![
+                record.id,
+                record.game_type.as_str(),
+                record.game_id,
+                record.player_name,
+                record.score,
+                record.reaction_time,
+                record.attempts,
+                record.duration_ms,
+                format_timestamp(&record.created_at),
+                record.difficulty.map(|difficulty| difficulty.as_str()),
+            ],
+        )?;
+        Ok(record)
+    }
+
+    fn get_leaderboard(&self, query: &LeaderboardQuery) -> Result<Vec<GameRecord>, AppError> {
+        let conn = lock_or_recover(&self.conn);
+        let filters = "(?1 IS NULL OR game_type = ?1)
+            AND (?2 IS NULL OR created_at >= ?2)
+            AND (?3 = 0 OR reaction_time IS NOT NULL)
+            AND (?5 IS NULL OR difficulty = ?5)";
+        // 每位玩家只保留排序后的第一条
+        let sql = if query.unique_players {
+            format!(
+                "SELECT {columns} FROM (
+                     SELECT *, ROW_NUMBER() OVER (PARTITION BY player_name ORDER BY {order}) AS player_rank
+                     FROM game_records WHERE {filters}
+                 ) WHERE player_rank = 1 ORDER BY {order} LIMIT ?4",
+                columns = RECORD_COLUMNS,
+                order = query.sort.order_by(),
+                filters = filters,
+            )
+        } else {
+            format!(
+                "SELECT {columns} FROM game_records WHERE {filters} ORDER BY {order} LIMIT ?4",
+                columns = RECORD_COLUMNS,
+                order = query.sort.order_by(),
+                filters = filters,
+            )
+        };
+
+        let mut stmt = conn.prepare(&sql)?;
+        let game_type = query.game_type.map(|game_type| game_type.as_str());
+        let since = query.window.since(Utc::now()).map(|since| format_timestamp(&since));
+        let reaction_only = query.sort == LeaderboardSort::ReactionTime;
+        let difficulty = query.difficulty.map(|difficulty| difficulty.as_str());
+        let records = stmt
+            .query_map(params![game_type, since, reaction_only, query.limit, difficulty], Self::row_to_record)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(records)
+    }
+
+    fn get_player_history(&self, player_name: &str, since: Option<DateTime<Utc>>, limit: i64) -> Result<Vec<GameRecord>, AppError> {
+        let conn = lock_or_recover(&self.conn);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM game_records WHERE player_name = ?1 AND (?2 IS NULL OR created_at >= ?2)
+             ORDER BY created_at DESC, id DESC LIMIT ?3",
+            RECORD_COLUMNS
+        ))?;
+        let since = since.map(|since| format_timestamp(&since));
+        let records = stmt
+            .query_map(params![player_name, since, limit], Self::row_to_record)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(records)
+    }
+
+    fn get_stats(&self) -> Result<(i64, i64), AppError> {
+        let conn = lock_or_recover(&self.conn);
+        conn.query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT player_name) FROM game_records",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(AppError::from)
+    }
+
+    fn is_connected(&self) -> bool {
+        let conn = lock_or_recover(&self.conn);
+        conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)).is_ok()
+    }
+
+    // 拿到连接锁即说明没有正在进行的写入，再把页缓存中的修改写回文件
+    fn flush(&self) -> Result<(), AppError> {
+        let conn = lock_or_recover(&self.conn);
+        conn.cache_flush()?;
+        Ok(())
+    }
+}
+
+// 数据库后端选择
+#[derive(Debug, Clone)]
+pub enum DatabaseBackend {
+    Memory,
+    Sqlite(PathBuf),
+}
+
+impl DatabaseBackend {
+    // memory 使用内存数据库，否则视为 SQLite 文件路径
+    pub fn parse(value: &str) -> Self {
+        if value.eq_ignore_ascii_case("memory") {
+            DatabaseBackend::Memory
+        } else {
+            DatabaseBackend::Sqlite(PathBuf::from(value))
+        }
+    }
+}
+
 // 同步数据库管理器
 pub struct SyncDatabaseManager {
-    inner: InMemoryDatabase,
+    inner: Box<dyn GameDatabase + Send + Sync>,
 }
 
 impl SyncDatabaseManager {
     pub fn new() -> Self {
         Self {
-            inner: InMemoryDatabase::new(),
+            inner: Box::new(InMemoryDatabase::new()),
         }
     }
+
+    pub fn open(backend: &DatabaseBackend) -> Result<Self, AppError> {
+        let inner: Box<dyn GameDatabase + Send + Sync> = match backend {
+            DatabaseBackend::Memory => Box::new(InMemoryDatabase::new()),
+            DatabaseBackend::Sqlite(path) => Box::new(SqliteDatabase::open(path)?),
+        };
+        Ok(Self { inner })
+    }
 }
 
 impl GameDatabase for SyncDatabaseManager {
-    fn save_game_record(&self, game_id: &str, player_name: &str, score: i32, reaction_time: Option<f64>) -> Result<(), String> {
-        self.inner.save_game_record(game_id, player_name, score, reaction_time)
+    fn save_game_record(&self, record: NewGameRecord) -> Result<GameRecord, AppError> {
+        self.inner.save_game_record(record)
     }
 
-    fn get_leaderboard(&self, limit: i64) -> Result<Vec<GameRecord>, String> {
-        self.inner.get_leaderboard(limit)
+    fn get_leaderboard(&self, query: &LeaderboardQuery) -> Result<Vec<GameRecord>, AppError> {
+        self.inner.get_leaderboard(query)
     }
 
-    fn get_player_history(&self, player_name: &str, limit: i64) -> Result<Vec<GameRecord>, String> {
-        self.inner.get_player_history(player_name, limit)
+    fn get_player_history(&self, player_name: &str, since: Option<DateTime<Utc>>, limit: i64) -> Result<Vec<GameRecord>, AppError> {
+        self.inner.get_player_history(player_name, since, limit)
     }
 
-    fn get_stats(&self) -> Result<(i64, i64), String> {
+    fn get_stats(&self) -> Result<(i64, i64), AppError> {
         self.inner.get_stats()
     }
 
     fn is_connected(&self) -> bool {
         self.inner.is_connected()
     }
-}
\ No newline at end of file
+
+    fn flush(&self) -> Result<(), AppError> {
+        self.inner.flush()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sqlite() -> SqliteDatabase {
+        SqliteDatabase::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn user_version(db: &SqliteDatabase) -> usize {
+        let conn = lock_or_recover(&db.conn);
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+    }
+
+    fn record(
+        game_type: GameType,
+        player_name: &str,
+        score: i32,
+        reaction_time: Option<f64>,
+        attempts: Option<u32>,
+        difficulty: Option<Difficulty>,
+        created_at: DateTime<Utc>,
+    ) -> GameRecord {
+        GameRecord {
+            id: new_record_id(),
+            game_type,
+            game_id: "game_1".to_string(),
+            player_name: player_name.to_string(),
+            score,
+            reaction_time,
+            attempts,
+            duration_ms: attempts.map(|attempts| attempts as u64 * 1000),
+            difficulty,
+            // 与数据库存储精度一致，便于比较
+            created_at: DateTime::parse_from_rfc3339(&format_timestamp(&created_at)).unwrap().with_timezone(&Utc),
+        }
+    }
+
+    // 指定创建时间写入记录，两种数据库得到完全相同的数据
+    fn insert(memory: &InMemoryDatabase, sqlite: &SqliteDatabase, record: &GameRecord) {
+        lock_or_recover(&memory.records).push(record.clone());
+        let conn = lock_or_recover(&sqlite.conn);
+        conn.execute(
+            &format!("INSERT INTO game_records ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", RECORD_COLUMNS),
+            params![
+                record.id,
+                record.game_type.as_str(),
+                record.game_id,
+                record.player_name,
+                record.score,
+                record.reaction_time,
+                record.attempts,
+                record.duration_ms,
+                format_timestamp(&record.created_at),
+                record.difficulty.map(|difficulty| difficulty.as_str()),
+            ],
+        )
+        .unwrap();
+    }
+
+    fn ids(records: &[GameRecord]) -> Vec<&str> {
+        records.iter().map(|r| r.id.as_str()).collect()
+    }
+
+    #[test]
+    fn fresh_databases_migrate_to_the_latest_version() {
+        let db = sqlite();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+        assert!(db.is_connected());
+        assert_eq!(db.get_stats().unwrap(), (0, 0));
+
+        // 再次迁移不做任何事
+        let conn = db.conn.into_inner().unwrap();
+        let db = SqliteDatabase::with_connection(conn).unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn v1_records_survive_every_migration() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        conn.execute_batch(
+            "INSERT INTO game_records (game_id, player_name, score, reaction_time, created_at)
+             VALUES ('game_1', '小明', 30, 245.5, '2024-05-01 12:00:00.5');
+             INSERT INTO game_records (game_id, player_name, score, reaction_time, created_at)
+             VALUES ('game_1', '小红', 10, NULL, '2024-05-01T12:00:01Z');",
+        )
+        .unwrap();
+
+        let db = SqliteDatabase::with_connection(conn).unwrap();
+        assert_eq!(user_version(&db), MIGRATIONS.len());
+
+        let records = db.get_leaderboard(&LeaderboardQuery::default()).unwrap();
+        assert_eq!(ids(&records), vec!["record_1", "record_2"]);
+        let first = &records[0];
+        assert_eq!(first.game_type, GameType::Racing);
+        assert_eq!((first.player_name.as_str(), first.score, first.reaction_time), ("小明", 30, Some(245.5)));
+        assert_eq!((first.attempts, first.duration_ms, first.difficulty), (None, None, None));
+        assert_eq!(format_timestamp(&first.created_at), "2024-05-01T12:00:00.500Z");
+        assert_eq!(format_timestamp(&records[1].created_at), "2024-05-01T12:00:01.000Z");
+    }
+
+    #[test]
+    fn v4_guess_records_default_to_normal_difficulty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..4] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 4).unwrap();
+        conn.execute_batch(
+            "INSERT INTO game_records (record_id, game_type, game_id, player_name, score, attempts, duration_ms, created_at)
+             VALUES ('record_a', 'guess_number', 'session_1', '小明', 60, 5, 3000, '2024-05-01T12:00:00.000Z');
+             INSERT INTO game_records (record_id, game_type, game_id, player_name, score, reaction_time, created_at)
+             VALUES ('record_b', 'racing', 'game_1', '小明', 20, 300.0, '2024-05-01T12:00:00.000Z');",
+        )
+        .unwrap();
+        SqliteDatabase::migrate(&mut conn).unwrap();
+        let db = SqliteDatabase { conn: Mutex::new(conn) };
+
+        let query = LeaderboardQuery { difficulty: Some(Difficulty::Normal), ..LeaderboardQuery::default() };
+        assert_eq!(ids(&db.get_leaderboard(&query).unwrap()), vec!["record_a"]);
+        let racing = LeaderboardQuery { game_type: Some(GameType::Racing), ..LeaderboardQuery::default() };
+        assert_eq!(db.get_leaderboard(&racing).unwrap()[0].difficulty, None);
+    }
+
+    #[test]
+    fn newer_schema_versions_are_refused() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        assert!(matches!(SqliteDatabase::with_connection(conn), Err(AppError::Database(_))));
+    }
+
+    #[test]
+    fn saved_records_read_back_unchanged() {
+        let db = sqlite();
+        let saved = db
+            .save_game_record(NewGameRecord {
+                game_type: GameType::GuessNumber,
+                game_id: "session_1".to_string(),
+                player_name: "小明".to_string(),
+                score: 70,
+                reaction_time: None,
+                attempts: Some(4),
+                duration_ms: Some(12_345),
+                difficulty: Some(Difficulty::Hard),
+            })
+            .unwrap();
+        db.save_game_record(NewGameRecord {
+            game_type: GameType::Racing,
+            game_id: "game_1".to_string(),
+            player_name: "小明".to_string(),
+            score: 25,
+            reaction_time: Some(187.25),
+            attempts: None,
+            duration_ms: None,
+            difficulty: None,
+        })
+        .unwrap();
+
+        let history = db.get_player_history("小明", None, 10).unwrap();
+        assert_eq!(history.len(), 2);
+        let loaded = history.iter().find(|r| r.id == saved.id).unwrap();
+        assert_eq!(loaded.game_type, GameType::GuessNumber);
+        assert_eq!((loaded.game_id.as_str(), loaded.player_name.as_str(), loaded.score), ("session_1", "小明", 70));
+        assert_eq!((loaded.reaction_time, loaded.attempts, loaded.duration_ms), (None, Some(4), Some(12_345)));
+        assert_eq!(loaded.difficulty, Some(Difficulty::Hard));
+        assert_eq!(format_timestamp(&loaded.created_at), format_timestamp(&saved.created_at));
+
+        let racing = history.iter().find(|r| r.id != saved.id).unwrap();
+        assert_eq!((racing.reaction_time, racing.difficulty), (Some(187.25), None));
+        assert_eq!(db.get_stats().unwrap(), (2, 1));
+        assert!(db.get_player_history("小红", None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn both_backends_answer_leaderboard_queries_the_same_way() {
+        let memory = InMemoryDatabase::new();
+        let sqlite = sqlite();
+        let now = Utc::now();
+        let today = TimeWindow::Daily.since(now).unwrap();
+        let week = TimeWindow::Weekly.since(now).unwrap();
+        let ms = Duration::milliseconds(1);
+        let (easy, normal) = (Some(Difficulty::Easy), Some(Difficulty::Normal));
+
+        let records = vec![
+            record(GameType::Racing, "小明", 30, Some(210.0), None, None, today),
+            record(GameType::Racing, "小明", 45, Some(180.5), None, None, today - ms),
+            record(GameType::Racing, "小红", 45, None, None, None, week),
+            record(GameType::Racing, "小红", 20, Some(320.0), None, None, week - ms),
+            record(GameType::Racing, "小刚", 30, Some(180.5), None, None, now - Duration::days(30)),
+            record(GameType::GuessNumber, "小明", 80, None, Some(3), normal, today + ms),
+            record(GameType::GuessNumber, "小红", 80, None, Some(2), normal, today),
+            record(GameType::GuessNumber, "小红", 90, None, Some(1), easy, week),
+            record(GameType::GuessNumber, "小刚", 80, None, Some(2), normal, now - Duration::days(30)),
+            record(GameType::GuessNumber, "小刚", 50, None, None, normal, today + ms * 2),
+        ];
+        for record in &records {
+            insert(&memory, &sqlite, record);
+        }
+
+        for game_type in [None, Some(GameType::Racing), Some(GameType::GuessNumber)] {
+            for difficulty in [None, normal, easy] {
+                for window in [TimeWindow::Daily, TimeWindow::Weekly, TimeWindow::AllTime] {
+                    for sort in [LeaderboardSort::Score, LeaderboardSort::ReactionTime] {
+                        for unique_players in [false, true] {
+                            for limit in [2, 20] {
+                                let query = LeaderboardQuery { game_type, difficulty, window, sort, unique_players, limit };
+                                let expected = memory.get_leaderboard(&query).unwrap();
+                                let actual = sqlite.get_leaderboard(&query).unwrap();
+                                assert_eq!(ids(&actual), ids(&expected), "{:?}", query);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // 抽查几个结果，确认比较的不是两个空列表
+        let query = LeaderboardQuery { sort: LeaderboardSort::ReactionTime, unique_players: true, ..LeaderboardQuery::default() };
+        let names: Vec<_> = sqlite.get_leaderboard(&query).unwrap().into_iter().map(|r| r.player_name).collect();
+        assert_eq!(names, vec!["小刚", "小明", "小红"]);
+        let query = LeaderboardQuery {
+            game_type: Some(GameType::GuessNumber),
+            difficulty: normal,
+            window: TimeWindow::Daily,
+            ..LeaderboardQuery::default()
+        };
+        assert_eq!(ids(&sqlite.get_leaderboard(&query).unwrap()), vec![&records[6].id, &records[5].id, &records[9].id]);
+
+        let since = Some(today);
+        assert_eq!(
+            ids(&sqlite.get_player_history("小红", since, 10).unwrap()),
+            ids(&memory.get_player_history("小红", since, 10).unwrap())
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::Cursor;
+use tiny_http::{Response, StatusCode};
+
+use crate::router::with_header;
+use crate::{GameState, GuessStatus};
+
+// 当前游戏状态下不允许的操作
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    IllegalTransition { from: GameState, to: GameState },
+    WrongState { expected: GameState, actual: GameState },
+    RoundsExhausted { max_rounds: u8 },
+    PlayersNotReady { players: Vec<u8> },
+    GuessFinished { status: GuessStatus }, // 猜数字本局已结束，需要重置
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::IllegalTransition { from, to } => write!(f, "游戏状态不能从 {:?} 变为 {:?}", from, to),
+            StateError::WrongState { expected, actual } => {
+                write!(f, "该操作需要游戏处于 {:?} 状态，当前状态: {:?}", expected, actual)
+            }
+            StateError::RoundsExhausted { max_rounds } => write!(f, "已完成全部 {} 个回合", max_rounds),
+            StateError::PlayersNotReady { players } => write!(f, "还有玩家未准备: {:?}", players),
+            StateError::GuessFinished { status } => {
+                let result = if *status == GuessStatus::Won { "已猜中" } else { "次数已用完" };
+                write!(f, "本局{}，请调用 /api/reset 开始新游戏", result)
+            }
+        }
+    }
+}
+
+// 请求体中某个字段的校验错误
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+// 全局错误类型，数据库、游戏存储和各个接口统一使用
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppError {
+    GameNotFound(String),
+    GameExpired(String),                             // 游戏空闲超时已被清理
+    TooManyGames(usize),                             // 同时进行的游戏数量达到上限
+    TooManyConnections(usize),                       // WebSocket 连接数量达到上限
+    SessionNotFound(String),
+    TooManySessions(usize),                          // 猜数字会话数量达到上限
+    MissingSession,
+    PlayerNotFound(u8),
+    AlreadyReacted(u8),
+    State(StateError),
+    MalformedBody(String),                           // 请求体不是合法的JSON或缺少字段
+    InvalidParameter { field: String, message: String },
+    Validation(Vec<FieldError>),                     // 请求体字段校验失败，列出所有出错的字段
+    Rejected(String),                                // 请求合法但被游戏规则拒绝，如时间戳不符
+    Forbidden(String),
+    NotFound(String),                                // 路径或静态文件不存在
+    MethodNotAllowed(String),
+    Database(String),
+    Internal(String),
+}
+
+// 错误响应体：{"code": "...", "message": "...", "details": {...}}
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    details: Option<Value>,
+}
+
+impl AppError {
+    pub fn invalid_parameter(field: &str, message: impl Into<String>) -> Self {
+        AppError::InvalidParameter {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+
+    // 稳定的机器可读错误码
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::GameNotFound(_) => "game_not_found",
+            AppError::GameExpired(_) => "game_expired",
+            AppError::TooManyGames(_) => "too_many_games",
+            AppError::TooManyConnections(_) => "too_many_connections",
+            AppError::SessionNotFound(_) => "session_not_found",
+            AppError::TooManySessions(_) => "too_many_sessions",
+            AppError::MissingSession => "missing_session",
+            AppError::PlayerNotFound(_) => "player_not_found",
+            AppError::AlreadyReacted(_) => "already_reacted",
+            AppError::State(StateError::IllegalTransition { .. }) => "illegal_transition",
+            AppError::State(StateError::WrongState { .. }) => "wrong_state",
+            AppError::State(StateError::RoundsExhausted { .. }) => "rounds_exhausted",
+            AppError::State(StateError::PlayersNotReady { .. }) => "players_not_ready",
+            AppError::State(StateError::GuessFinished { .. }) => "guess_finished",
+            AppError::MalformedBody(_) => "malformed_body",
+            AppError::InvalidParameter { .. } => "invalid_parameter",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Rejected(_) => "rejected",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::MethodNotAllowed(_) => "method_not_allowed",
+            AppError::Database(_) => "database_error",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            AppError::GameNotFound(_) | AppError::SessionNotFound(_) | AppError::NotFound(_) => 404,
+            AppError::GameExpired(_) => 410,
+            AppError::AlreadyReacted(_) | AppError::State(_) => 409,
+            AppError::Forbidden(_) => 403,
+            AppError::MethodNotAllowed(_) => 405,
+            AppError::Database(_) | AppError::Internal(_) => 500,
+            AppError::TooManyGames(_) | AppError::TooManySessions(_) | AppError::TooManyConnections(_) => 503,
+            AppError::MissingSession
+            | AppError::PlayerNotFound(_)
+            | AppError::MalformedBody(_)
+            | AppError::InvalidParameter { .. }
+            | AppError::Validation(_)
+            | AppError::Rejected(_) => 400,
+        }
+    }
+
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            AppError::GameNotFound(game_id) | AppError::GameExpired(game_id) => Some(json!({ "game_id": game_id })),
+            AppError::TooManyGames(max_games) => Some(json!({ "max_games": max_games })),
+            AppError::TooManyConnections(max_connections) => Some(json!({ "max_connections": max_connections })),
+            AppError::SessionNotFound(session_id) => Some(json!({ "session_id": session_id })),
+            AppError::TooManySessions(max_sessions) => Some(json!({ "max_sessions": max_sessions })),
+            AppError::PlayerNotFound(player_id) | AppError::AlreadyReacted(player_id) => {
+                Some(json!({ "player_id": player_id }))
+            }
+            AppError::State(StateError::IllegalTransition { from, to }) => Some(json!({ "from": from, "to": to })),
+            AppError::State(StateError::WrongState { expected, actual }) => {
+                Some(json!({ "expected": expected, "actual": actual }))
+            }
+            AppError::State(StateError::RoundsExhausted { max_rounds }) => Some(json!({ "max_rounds": max_rounds })),
+            AppError::State(StateError::PlayersNotReady { players }) => Some(json!({ "players": players })),
+            AppError::State(StateError::GuessFinished { status }) => Some(json!({ "status": status })),
+            AppError::InvalidParameter { field, .. } => Some(json!({ "field": field })),
+            AppError::Validation(errors) => Some(json!({ "errors": errors })),
+            AppError::NotFound(path) => Some(json!({ "path": path })),
+            AppError::MethodNotAllowed(method) => Some(json!({ "method": method })),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            details: self.details(),
+        };
+        serde_json::to_string(&body).unwrap_or_else(|_| {
+            format!("{{\"code\":\"{}\",\"message\":null,\"details\":null}}", self.code())
+        })
+    }
+
+    pub fn to_response(&self) -> Response<Cursor<Vec<u8>>> {
+        with_header(Response::from_string(self.to_json()), "Content-Type", "application/json")
+            .with_status_code(StatusCode::from(self.status()))
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::GameNotFound(_) => write!(f, "游戏未找到"),
+            AppError::GameExpired(_) => write!(f, "游戏长时间没有操作，已过期"),
+            AppError::TooManyGames(max_games) => write!(f, "同时进行的游戏已达上限 {} 局，请稍后再试", max_games),
+            AppError::TooManyConnections(max_connections) => {
+                write!(f, "实时连接已达上限 {} 个，请稍后再试或改用轮询", max_connections)
+            }
+            AppError::SessionNotFound(_) => write!(f, "会话未找到"),
+            AppError::TooManySessions(max_sessions) => write!(f, "游戏会话已达上限 {} 个，请稍后再试", max_sessions),
+            AppError::MissingSession => write!(f, "缺少会话ID，请先调用 /api/session"),
+            AppError::PlayerNotFound(player_id) => write!(f, "玩家ID {} 不存在", player_id),
+            AppError::AlreadyReacted(player_id) => write!(f, "玩家 {} 本回合已反应", player_id),
+            AppError::State(error) => write!(f, "{}", error),
+            AppError::MalformedBody(error) => write!(f, "请求格式错误: {}", error),
+            AppError::InvalidParameter { field, message } => write!(f, "参数 {} 无效: {}", field, message),
+            AppError::Validation(errors) => {
+                let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+                write!(f, "请求参数校验失败: {}", fields.join(", "))
+            }
+            AppError::Rejected(message) | AppError::Forbidden(message) => write!(f, "{}", message),
+            AppError::NotFound(path) => write!(f, "{} 不存在", path),
+            AppError::MethodNotAllowed(method) => write!(f, "该路径不支持 {} 请求", method),
+            AppError::Database(error) => write!(f, "数据库错误: {}", error),
+            AppError::Internal(error) => write!(f, "服务器内部错误: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<StateError> for AppError {
+    fn from(error: StateError) -> Self {
+        AppError::State(error)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(error: serde_json::Error) -> Self {
+        AppError::MalformedBody(error.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(error: rusqlite::Error) -> Self {
+        AppError::Database(error.to_string())
+    }
+}
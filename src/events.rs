@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+use crate::{lock_or_recover, GameResponse, GameState, PlayerRoundResult, RoundResult};
+
+// 推送给WebSocket客户端的赛车游戏事件
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RacingEvent {
+    StateChange { game: GameResponse },
+    LightChange { game_state: GameState, red_lights: u8 },
+    PlayerResult { result: PlayerRoundResult },
+    RoundResult { result: RoundResult },
+    GameExpired { game_id: String }, // 游戏空闲超时被清理，随后连接关闭
+}
+
+// 按游戏ID分发事件的订阅中心
+pub struct EventHub {
+    subscribers: Mutex<HashMap<String, Vec<Sender<String>>>>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn subscribe(&self, game_id: &str) -> Receiver<String> {
+        let (sender, receiver) = channel();
+        let mut subscribers = lock_or_recover(&self.subscribers);
+        subscribers.entry(game_id.to_string()).or_default().push(sender);
+        receiver
+    }
+
+    // 序列化一次后发给所有订阅者，顺便清理已断开的连接
+    pub fn publish(&self, game_id: &str, event: &RacingEvent) {
+        let mut subscribers = lock_or_recover(&self.subscribers);
+        if let Some(senders) = subscribers.get_mut(game_id) {
+            let message = match serde_json::to_string(event) {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("事件序列化失败: {}", e);
+                    return;
+                }
+            };
+            senders.retain(|sender| sender.send(message.clone()).is_ok());
+            if senders.is_empty() {
+                subscribers.remove(game_id);
+            }
+        }
+    }
+
+    // 移除游戏的所有订阅者，对应的WebSocket连接随之关闭
+    pub fn close(&self, game_id: &str) {
+        lock_or_recover(&self.subscribers).remove(game_id);
+    }
+}
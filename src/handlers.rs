@@ -0,0 +1,394 @@
+use std::sync::Arc;
+use tiny_http::Method;
+
+use crate::database::{GameDatabase, GameType, LeaderboardQuery, LeaderboardSort, SyncDatabaseManager, TimeWindow};
+use crate::error::AppError;
+use crate::router::{json_response, HandlerResult, RouteContext, Router};
+use crate::static_files::StaticFiles;
+use crate::{
+    ClockSyncRequest, CreateGameRequest, CreateSessionRequest, Difficulty, GameListResponse, GameStateStorage, GuessSessionStorage,
+    LeaderboardEntry, ReactionRequest, ReadyRequest,
+};
+
+// 各个处理函数共享的服务状态
+pub struct AppState {
+    pub guess_sessions: Arc<GuessSessionStorage>,
+    pub racing: Arc<GameStateStorage>,
+    pub database: Arc<SyncDatabaseManager>,
+    pub static_files: StaticFiles,
+}
+
+// 排行榜和历史记录单次最多返回的条数
+const MAX_LIMIT: i64 = 100;
+
+// 新增游戏时在这里注册它的路由
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        // 猜数字
+        .route(Method::Post, "/api/session", create_session)
+        .route(Method::Get, "/api/info", guess_info)
+        .route(Method::Get, "/api/guess/leaderboard", guess_leaderboard)
+        .route(Method::Get, "/api/guess/{number}", guess)
+        .route(Method::Post, "/api/reset", reset_guess)
+        // 公共
+        .route(Method::Get, "/api/games", list_games)
+        .route(Method::Get, "/api/leaderboard", leaderboard)
+        // 赛车
+        .route(Method::Post, "/api/racing/create", create_racing_game)
+        .route(Method::Post, "/api/racing/start/{game_id}", start_racing_round)
+        .route(Method::Post, "/api/racing/ready", set_player_ready)
+        .route(Method::Post, "/api/racing/trigger/{game_id}", trigger_green_light)
+        .route(Method::Post, "/api/racing/sync", sync_clock)
+        .route(Method::Post, "/api/racing/react", record_reaction)
+        .route(Method::Post, "/api/racing/finish/{game_id}", finish_racing_round)
+        .route(Method::Get, "/api/racing/status/{game_id}", racing_status)
+        // 数据库
+        .route(Method::Get, "/api/database/player/{player_name}", player_history)
+        .route(Method::Get, "/api/database/stats", database_stats)
+        // 页面和静态文件
+        .route(Method::Get, "/", index)
+        .route(Method::Get, "/{*path}", static_file)
+}
+
+// 从 X-Session-Id 请求头或 session_id 查询参数中读取会话ID
+fn session_id(ctx: &RouteContext) -> Result<String, AppError> {
+    ctx.header("X-Session-Id")
+        .or_else(|| ctx.query("session_id").map(str::to_string))
+        .filter(|id| !id.is_empty())
+        .ok_or(AppError::MissingSession)
+}
+
+// 解析 limit 查询参数，默认10条，须在 1 到 MAX_LIMIT 之间
+fn limit_param(ctx: &RouteContext) -> Result<i64, AppError> {
+    let limit = ctx.query_parsed("limit")?.unwrap_or(10);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(AppError::invalid_parameter("limit", format!("须在 1 到 {} 之间", MAX_LIMIT)));
+    }
+    Ok(limit)
+}
+
+// 解析排行榜查询参数：game、difficulty、period、sort、unique、limit
+// 不同游戏、不同难度的积分不能放在一起比较：未指定 game 时为赛车排行榜，
+// 指定 difficulty 时为猜数字排行榜，猜数字未指定难度时为 normal
+fn leaderboard_query(ctx: &RouteContext) -> Result<LeaderboardQuery, AppError> {
+    let mut query = LeaderboardQuery::default();
+    let game = ctx
+        .query("game")
+        .map(|value| {
+            GameType::parse(value).ok_or_else(|| AppError::invalid_parameter("game", format!("未知的游戏类型: {}", value)))
+        })
+        .transpose()?;
+    let difficulty = ctx
+        .query("difficulty")
+        .map(|value| match Difficulty::parse(value) {
+            Some(Difficulty::Custom) => Err(AppError::invalid_parameter("difficulty", "自定义难度不参与排行")),
+            Some(difficulty) => Ok(difficulty),
+            None => Err(AppError::invalid_parameter(
+                "difficulty",
+                format!("须为 easy、normal、hard 或 expert: {}", value),
+            )),
+        })
+        .transpose()?;
+    match (game, difficulty) {
+        (Some(GameType::Racing), Some(_)) => {
+            return Err(AppError::invalid_parameter("difficulty", "只适用于 guess_number"));
+        }
+        (Some(GameType::Racing), None) | (None, None) => query.game_type = Some(GameType::Racing),
+        (Some(GameType::GuessNumber), _) | (None, Some(_)) => {
+            query.game_type = Some(GameType::GuessNumber);
+            query.difficulty = Some(difficulty.unwrap_or_default());
+        }
+    }
+    if let Some(value) = ctx.query("period") {
+        query.window = TimeWindow::parse(value)
+            .ok_or_else(|| AppError::invalid_parameter("period", format!("须为 daily、weekly 或 all: {}", value)))?;
+    }
+    if let Some(value) = ctx.query("sort") {
+        query.sort = LeaderboardSort::parse(value)
+            .ok_or_else(|| AppError::invalid_parameter("sort", format!("须为 score 或 reaction_time: {}", value)))?;
+    }
+    if let Some(value) = ctx.query("unique") {
+        query.unique_players = matches!(value, "true" | "1");
+    }
+    query.limit = limit_param(ctx)?;
+    Ok(query)
+}
+
+fn create_session(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    // 请求体可省略，此时以匿名玩家身份开始普通难度
+    let content = ctx.body()?;
+    let req: CreateSessionRequest = if content.trim().is_empty() {
+        CreateSessionRequest::default()
+    } else {
+        serde_json::from_str(&content)?
+    };
+    json_response(&state.guess_sessions.create_session(req)?)
+}
+
+fn guess_info(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    json_response(&state.guess_sessions.get_info(&session_id(ctx)?)?)
+}
+
+fn guess_leaderboard(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    // 不同难度的成绩不放在一起比较，未指定时显示普通难度
+    let query = leaderboard_query(ctx)?;
+    let query = LeaderboardQuery {
+        game_type: Some(GameType::GuessNumber),
+        difficulty: Some(query.difficulty.unwrap_or_default()),
+        sort: LeaderboardSort::Score,
+        ..query
+    };
+    let entries: Vec<LeaderboardEntry> = state
+        .database
+        .get_leaderboard(&query)?
+        .into_iter()
+        .map(LeaderboardEntry::from)
+        .collect();
+    json_response(&entries)
+}
+
+fn guess(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    let number = ctx.param("number");
+    let number = number
+        .parse::<u32>()
+        .map_err(|_| AppError::invalid_parameter("number", format!("须为正整数: {}", number)))?;
+    json_response(&state.guess_sessions.guess(&session_id(ctx)?, number)?)
+}
+
+fn reset_guess(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    json_response(&state.guess_sessions.reset(&session_id(ctx)?)?)
+}
+
+fn list_games(_ctx: &mut RouteContext, _state: &AppState) -> HandlerResult {
+    let games = vec!["猜数字游戏".to_string(), "赛车起跑反应".to_string()];
+    json_response(&GameListResponse { games })
+}
+
+fn leaderboard(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    json_response(&state.database.get_leaderboard(&leaderboard_query(ctx)?)?)
+}
+
+fn create_racing_game(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    // 请求体为空时按配置创建默认游戏；格式错误时返回错误，不再悄悄改用默认游戏
+    let content = ctx.body()?;
+    let req: CreateGameRequest = if content.trim().is_empty() {
+        state.racing.config().default_game.clone()
+    } else {
+        serde_json::from_str(&content)?
+    };
+    json_response(&state.racing.create_game(req)?)
+}
+
+fn start_racing_round(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    json_response(&state.racing.start_game(ctx.param("game_id"))?)
+}
+
+fn set_player_ready(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    let req: ReadyRequest = ctx.json()?;
+    json_response(&state.racing.set_ready(&req)?)
+}
+
+fn trigger_green_light(_ctx: &mut RouteContext, _state: &AppState) -> HandlerResult {
+    // 绿灯由服务器调度，不接受客户端触发
+    Err(AppError::Forbidden("信号灯由服务器控制，不能手动触发".to_string()))
+}
+
+fn sync_clock(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    let req: ClockSyncRequest = ctx.json()?;
+    json_response(&state.racing.sync_clock(&req, ctx.received_at)?)
+}
+
+fn record_reaction(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    let req: ReactionRequest = ctx.json()?;
+    json_response(&state.racing.record_reaction(&req, ctx.received_at)?)
+}
+
+fn finish_racing_round(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    json_response(&state.racing.finish_round(ctx.param("game_id"))?)
+}
+
+fn racing_status(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    json_response(&state.racing.get_game(ctx.param("game_id"))?)
+}
+
+fn player_history(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    let limit = limit_param(ctx)?;
+    // 可选的起始时间过滤，RFC 3339 格式，如 since=2024-01-01T00:00:00Z
+    let since = ctx
+        .query("since")
+        .map(|since| {
+            chrono::DateTime::parse_from_rfc3339(since)
+                .map(|t| t.with_timezone(&chrono::Utc))
+                .map_err(|_| AppError::invalid_parameter("since", "须为 RFC 3339 时间格式"))
+        })
+        .transpose()?;
+    json_response(&state.database.get_player_history(ctx.param("player_name"), since, limit)?)
+}
+
+fn database_stats(_ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    let (total_records, total_players) = state.database.get_stats()?;
+    json_response(&serde_json::json!({
+        "total_records": total_records,
+        "total_players": total_players,
+        "status": if state.database.is_connected() { "connected" } else { "disconnected" }
+    }))
+}
+
+fn index(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    state.static_files.serve(ctx, "index.html")
+}
+
+fn static_file(ctx: &mut RouteContext, state: &AppState) -> HandlerResult {
+    state.static_files.serve(ctx, ctx.param("path"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GuessConfig, RacingConfig};
+    use serde_json::Value;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::time::Instant;
+    use tiny_http::Server;
+
+    fn app_state() -> AppState {
+        let database = Arc::new(SyncDatabaseManager::new());
+        AppState {
+            guess_sessions: Arc::new(GuessSessionStorage::new(database.clone(), GuessConfig::default())),
+            racing: Arc::new(GameStateStorage::with_database(database.clone(), RacingConfig::default())),
+            database,
+            static_files: StaticFiles::new(None),
+        }
+    }
+
+    // 通过真实的TCP连接发送原始请求字节，再交给路由处理，返回状态码和JSON响应体
+    fn send(state: &AppState, raw: &[u8]) -> (u16, Value) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(raw).unwrap();
+        let mut request = server.recv().unwrap();
+        let response = routes().dispatch(&mut request, state, Instant::now());
+        let status = response.status_code().0;
+        // 页面和静态文件不是JSON，只比较状态码
+        let body = serde_json::from_reader(response.into_reader()).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    fn get(state: &AppState, path: &str) -> (u16, Value) {
+        send(state, format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+    }
+
+    fn post(state: &AppState, path: &str, body: &[u8]) -> (u16, Value) {
+        let mut raw = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", path, body.len()).into_bytes();
+        raw.extend_from_slice(body);
+        send(state, &raw)
+    }
+
+    fn assert_error(response: (u16, Value), status: u16, code: &str) {
+        assert_eq!(response.0, status, "{}", response.1);
+        assert_eq!(response.1["code"], code, "{}", response.1);
+        assert!(response.1["message"].is_string());
+    }
+
+    #[test]
+    fn non_utf8_bodies_are_rejected() {
+        let state = app_state();
+        assert_error(post(&state, "/api/racing/create", &[0xff, 0xfe, 0x00, 0x80]), 400, "malformed_body");
+        assert_error(post(&state, "/api/racing/react", &[b'{', 0xc3, 0x28, b'}']), 400, "malformed_body");
+        assert_error(post(&state, "/api/session", &[0xff]), 400, "malformed_body");
+    }
+
+    #[test]
+    fn invalid_json_is_rejected() {
+        let state = app_state();
+        assert_error(post(&state, "/api/racing/create", b"not json"), 400, "malformed_body");
+        assert_error(post(&state, "/api/racing/react", br#"{"game_id": "#), 400, "malformed_body");
+        assert_error(post(&state, "/api/racing/ready", br#"{"game_id": 1, "player_id": "x"}"#), 400, "malformed_body");
+        assert_error(post(&state, "/api/session", b"[1, 2"), 400, "malformed_body");
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_rejected() {
+        let state = app_state();
+        let (status, body) =
+            post(&state, "/api/racing/create", br#"{"player_count": 300, "round_count": 3, "player_names": []}"#);
+        assert_error((status, body.clone()), 400, "validation_failed");
+        assert_eq!(body["details"]["errors"][0]["field"], "player_count", "{}", body);
+        assert_error(post(&state, "/api/racing/create", br#"{"player_count": 1e3, "round_count": 3, "player_names": []}"#), 400, "malformed_body");
+        assert_error(
+            post(&state, "/api/racing/create", br#"{"player_count": 9, "round_count": 0, "player_names": []}"#),
+            400,
+            "validation_failed",
+        );
+        assert_error(get(&state, "/api/leaderboard?limit=99999999999999999999"), 400, "invalid_parameter");
+        assert_error(get(&state, "/api/leaderboard?limit=-1"), 400, "invalid_parameter");
+
+        let (status, session) = post(&state, "/api/session", b"");
+        assert_eq!(status, 200);
+        let session_id = session["session_id"].as_str().unwrap();
+        for number in ["99999999999999999999", "-5", "0", "101"] {
+            let path = format!("/api/guess/{}?session_id={}", number, session_id);
+            assert_error(get(&state, &path), 400, "invalid_parameter");
+        }
+        let (_, info) = get(&state, &format!("/api/info?session_id={}", session_id));
+        assert_eq!(info["attempts"], 0);
+    }
+
+    #[test]
+    fn invalid_percent_escapes_in_paths_are_handled() {
+        let state = app_state();
+        assert_error(get(&state, "/api/racing/status/%ff%fe%"), 404, "game_not_found");
+        assert_error(post(&state, "/api/racing/start/%ff%fe%", b""), 404, "game_not_found");
+        assert_error(get(&state, "/api/guess/%ff%fe%?session_id=%ff%fe%"), 400, "invalid_parameter");
+        assert_error(get(&state, "/api/leaderboard?game=%ff%fe%"), 400, "invalid_parameter");
+        let (status, body) = get(&state, "/%ff%fe%/..%2f..%2fCargo.toml");
+        assert!((400..500).contains(&status), "{} {}", status, body);
+        assert!(body["code"].is_string());
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found_for_every_method() {
+        let state = app_state();
+        assert_error(post(&state, "/api/nope", b""), 404, "not_found");
+        assert_error(send(&state, b"DELETE /racing-game.js HTTP/1.1\r\nHost: localhost\r\n\r\n"), 404, "not_found");
+        // 已注册的路径仍然区分请求方法
+        assert_error(get(&state, "/api/racing/create"), 405, "method_not_allowed");
+        assert_error(post(&state, "/api/leaderboard", b""), 405, "method_not_allowed");
+    }
+
+    #[test]
+    fn head_requests_use_get_routes() {
+        let state = app_state();
+        let head = |path: &str| send(&state, format!("HEAD {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes());
+        assert_eq!(head("/").0, 200);
+        assert_eq!(head("/api/leaderboard").0, 200);
+        assert_error(head("/api/nope"), 404, "not_found");
+        assert_error(head("/api/racing/create"), 405, "method_not_allowed");
+    }
+
+    #[test]
+    fn poisoned_game_lock_does_not_break_later_requests() {
+        let state = app_state();
+        let (status, created) = post(&state, "/api/racing/create", b"");
+        assert_eq!(status, 200);
+        let game_id = created["game_id"].as_str().unwrap().to_string();
+
+        let games = Arc::clone(&state.racing.games);
+        let poisoner = std::thread::spawn(move || {
+            let _guard = games.lock().unwrap();
+            panic!("持锁线程崩溃");
+        });
+        assert!(poisoner.join().is_err());
+        assert!(state.racing.games.is_poisoned());
+
+        let (status, game) = get(&state, &format!("/api/racing/status/{}", game_id));
+        assert_eq!(status, 200, "{}", game);
+        assert_eq!(game["game_id"], game_id.as_str());
+        // 第一次恢复后污染标记已清除，之后的请求不会再次报警
+        assert!(!state.racing.games.is_poisoned());
+        let (status, _) = post(&state, "/api/racing/create", b"");
+        assert_eq!(status, 200);
+    }
+}
@@ -1,16 +1,79 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
-use tiny_http::{Header, Method, Response, Server, StatusCode};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Method, Server};
 
+mod config;
 mod database;
+mod error;
+mod events;
+mod handlers;
+mod router;
+mod scoring;
+mod snapshot;
+mod static_files;
+mod websocket;
 
-use database::{GameDatabase, SyncDatabaseManager};
+use config::{Config, GuessConfig, RaceTiming, RacingConfig};
+use database::{
+    DatabaseBackend, GameDatabase, GameRecord, GameType, NewGameRecord, SyncDatabaseManager,
+};
+use error::{AppError, FieldError, StateError};
+use events::{EventHub, RacingEvent};
+use handlers::AppState;
+use router::Router;
+use static_files::StaticFiles;
+use scoring::{ScoringConfig, ScoringRules};
 
 use rand::Rng;
 
+// 红灯数量及亮灯间隔
+const RED_LIGHT_COUNT: u8 = 5;
+const RED_LIGHT_INTERVAL: Duration = Duration::from_secs(1);
+// 信号灯调度器轮询间隔
+const SCHEDULER_TICK: Duration = Duration::from_millis(5);
+// 过期游戏清理线程的检查间隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+// 已清理的游戏ID保留多久，期间访问返回 game_expired 而不是 game_not_found
+const EXPIRED_GAME_RETENTION: Duration = Duration::from_secs(3600);
+// 工作线程等待请求的间隔，也是发现退出信号的最长延迟
+const SHUTDOWN_POLL: Duration = Duration::from_millis(200);
+// 收到退出信号后最多再处理这么久已排队的请求，持续有新请求时也会按时退出
+const SHUTDOWN_DRAIN_LIMIT: Duration = Duration::from_secs(2);
+
+// 红灯全亮后到绿灯亮起的随机等待时间（1.5-5秒）
+fn get_random_delay() -> Duration {
+    let mut rng = rand::thread_rng();
+    let delay_ms = rng.gen_range(1500..=5000);
+    Duration::from_millis(delay_ms)
+}
+
+// 服务器单调时钟（毫秒），用于与客户端时钟对时
+fn server_clock_ms(at: Instant) -> f64 {
+    static SERVER_EPOCH: OnceLock<Instant> = OnceLock::new();
+    let epoch = *SERVER_EPOCH.get_or_init(Instant::now);
+    if at >= epoch {
+        at.duration_since(epoch).as_secs_f64() * 1000.0
+    } else {
+        -(epoch.duration_since(at).as_secs_f64() * 1000.0)
+    }
+}
+
+// 获取互斥锁；持锁线程崩溃导致锁被污染时继续使用其中的数据，避免一次崩溃拖垮整个服务。
+// 恢复后清除污染标记，警告只输出一次
+pub fn lock_or_recover<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        println!("⚠️ 检测到被污染的锁，继续使用其中的数据");
+        let guard = poisoned.into_inner();
+        mutex.clear_poison();
+        guard
+    })
+}
+
 // 游戏状态枚举
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -23,6 +86,22 @@ pub enum GameState {
     GameOver,
 }
 
+impl GameState {
+    // 状态转换表：每个回合 Waiting → Countdown → Ready → Racing → Finished，
+    // 之后回到 Waiting 开始下一回合，或在最后一回合后进入 GameOver
+    pub fn can_transition_to(&self, next: &GameState) -> bool {
+        matches!(
+            (self, next),
+            (GameState::Waiting, GameState::Countdown)
+                | (GameState::Countdown, GameState::Ready)
+                | (GameState::Ready, GameState::Racing)
+                | (GameState::Racing, GameState::Finished)
+                | (GameState::Finished, GameState::Waiting)
+                | (GameState::Finished, GameState::GameOver)
+        )
+    }
+}
+
 // 玩家数据结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
@@ -58,15 +137,25 @@ pub struct RacingGame {
     pub current_round: u8,
     pub max_rounds: u8,
     pub round_results: Vec<RoundResult>,
-    #[serde(skip)]
+    pub scoring: ScoringRules,
+    pub auto_start: bool, // 所有玩家准备后自动开始回合
+    pub timing: RaceTiming,
+    pub red_lights: u8, // 当前亮起的红灯数量
+    #[serde(with = "snapshot::optional_instant")]
+    pub next_light_at: Option<Instant>, // 信号灯下一次切换的时刻
+    #[serde(with = "snapshot::optional_instant")]
     pub green_light_time: Option<Instant>,
     pub reacted_players: Vec<u8>,
-    #[serde(skip)]
     pub player_reactions: HashMap<u8, f64>, // 存储玩家反应时间
+    pub clock_offsets: HashMap<u8, f64>, // 玩家时钟偏移（服务器时钟 - 客户端时钟）
+    #[serde(default)]
+    pub clock_round_trips: HashMap<u8, f64>, // 得到上述偏移的那次对时的往返时间
+    #[serde(with = "snapshot::instant")]
+    pub last_activity: Instant, // 最近一次玩家操作的时刻，空闲超时后游戏被清理
 }
 
 impl RacingGame {
-    pub fn new(player_count: u8, round_count: u8, player_names: Vec<String>) -> Self {
+    pub fn new(player_count: u8, round_count: u8, player_names: Vec<String>, scoring: ScoringRules, auto_start: bool, timing: RaceTiming) -> Self {
         let players: Vec<Player> = (1..=player_count)
             .map(|i| Player {
                 id: i,
@@ -90,44 +179,220 @@ impl RacingGame {
             current_round: 0,
             max_rounds: round_count,
             round_results: Vec::new(),
+            scoring,
+            auto_start,
+            timing,
+            red_lights: 0,
+            next_light_at: None,
             green_light_time: None,
             reacted_players: Vec::new(),
             player_reactions: HashMap::new(),
+            clock_offsets: HashMap::new(),
+            clock_round_trips: HashMap::new(),
+            last_activity: Instant::now(),
+        }
+    }
+
+    pub fn to_response(&self) -> GameResponse {
+        GameResponse {
+            game_id: self.game_id.clone(),
+            game_state: self.game_state.clone(),
+            players: self.players.clone(),
+            current_round: self.current_round,
+            max_rounds: self.max_rounds,
+            round_results: self.round_results.clone(),
+            scoring: self.scoring.clone(),
+            auto_start: self.auto_start,
+            red_lights: self.red_lights,
         }
     }
 
-    pub fn start_round(&mut self) {
-        self.game_state = GameState::Countdown;
+    // 等待状态下设置玩家的准备状态
+    pub fn set_ready(&mut self, player_id: u8, ready: bool) -> Result<(), AppError> {
+        self.expect_state(GameState::Waiting)?;
+        let player = self
+            .players
+            .iter_mut()
+            .find(|p| p.id == player_id)
+            .ok_or(AppError::PlayerNotFound(player_id))?;
+        player.is_ready = ready;
+        Ok(())
+    }
+
+    // 从快照恢复后调用：进行中的回合依赖信号灯计时和对时结果，无法继续，
+    // 回到等待状态重新开始本回合（已完成的回合和积分保留）
+    pub fn rollback_interrupted_round(&mut self) {
+        if matches!(
+            self.game_state,
+            GameState::Countdown | GameState::Ready | GameState::Racing | GameState::Finished
+        ) {
+            self.game_state = GameState::Waiting;
+            self.red_lights = 0;
+            self.next_light_at = None;
+            self.green_light_time = None;
+            self.reacted_players.clear();
+            self.player_reactions.clear();
+            for player in &mut self.players {
+                player.is_ready = false;
+            }
+        }
+        // 服务器时钟在重启后重新计时，之前的偏移已经无效，需要重新对时
+        self.clock_offsets.clear();
+        self.clock_round_trips.clear();
+        self.last_activity = Instant::now();
+    }
+
+    pub fn all_ready(&self) -> bool {
+        self.players.iter().all(|p| p.is_ready)
+    }
+
+    fn expect_state(&self, expected: GameState) -> Result<(), StateError> {
+        if self.game_state != expected {
+            return Err(StateError::WrongState { expected, actual: self.game_state.clone() });
+        }
+        Ok(())
+    }
+
+    // 按状态转换表切换状态，非法转换不修改游戏
+    fn transition(&mut self, next: GameState) -> Result<(), StateError> {
+        if !self.game_state.can_transition_to(&next) {
+            return Err(StateError::IllegalTransition { from: self.game_state.clone(), to: next });
+        }
+        self.game_state = next;
+        Ok(())
+    }
+
+    // 所有玩家准备后才能从等待状态进入倒计时
+    pub fn start_round(&mut self) -> Result<(), AppError> {
+        if self.current_round >= self.max_rounds {
+            return Err(StateError::RoundsExhausted { max_rounds: self.max_rounds }.into());
+        }
+        if self.game_state == GameState::Waiting && !self.all_ready() {
+            let players = self.players.iter().filter(|p| !p.is_ready).map(|p| p.id).collect();
+            return Err(StateError::PlayersNotReady { players }.into());
+        }
+        self.transition(GameState::Countdown)?;
+
+        // 每个回合都需要重新准备
+        for player in &mut self.players {
+            player.is_ready = false;
+        }
+        self.red_lights = 0;
+        self.next_light_at = Some(Instant::now() + RED_LIGHT_INTERVAL);
         self.green_light_time = None;
         self.reacted_players.clear();
         self.player_reactions.clear();
+        Ok(())
     }
 
-    pub fn trigger_green_light(&mut self) {
-        self.game_state = GameState::Racing;
-        self.green_light_time = Some(Instant::now());
+    // 按时间推进信号灯：红灯每秒亮一盏，全亮后随机等待，再转为绿灯
+    pub fn advance_lights(&mut self, now: Instant) {
+        while let Some(deadline) = self.next_light_at {
+            if deadline > now {
+                break;
+            }
+
+            match self.game_state {
+                GameState::Countdown if self.red_lights + 1 >= RED_LIGHT_COUNT => {
+                    self.red_lights = RED_LIGHT_COUNT;
+                    self.next_light_at = Some(deadline + get_random_delay());
+                    if self.transition(GameState::Ready).is_err() {
+                        self.next_light_at = None;
+                    }
+                }
+                GameState::Countdown => {
+                    self.red_lights += 1;
+                    self.next_light_at = Some(deadline + RED_LIGHT_INTERVAL);
+                }
+                // 以计划时刻作为绿灯时间，不受调度延迟影响
+                GameState::Ready => {
+                    if self.trigger_green_light(deadline).is_err() {
+                        self.next_light_at = None;
+                    }
+                }
+                _ => self.next_light_at = None,
+            }
+        }
+    }
+
+    pub fn trigger_green_light(&mut self, at: Instant) -> Result<(), AppError> {
+        self.transition(GameState::Racing)?;
+        self.red_lights = 0;
+        self.next_light_at = None;
+        self.green_light_time = Some(at);
+        Ok(())
     }
 
-    pub fn record_reaction(&mut self, player_id: u8, reaction_time: f64) -> Result<PlayerRoundResult, String> {
-        println!("Debug: record_reaction called with player_id={}, reaction_time={}", player_id, reaction_time);
-        println!("Debug: game_state={:?}, reacted_players={:?}", self.game_state, self.reacted_players);
-        
-        if self.game_state != GameState::Racing {
-            return Err(format!("游戏不在起跑状态，当前状态: {:?}", self.game_state));
+    // 记录一次完整往返的对时采样：偏移 = t1 - (t0 + t2) / 2，假定去程和回程延迟相同；
+    // 保留往返时间最短的那次采样，它的误差最小
+    pub fn sync_clock(&mut self, player_id: u8, sample: Option<&ClockSample>, received_at: Instant) -> Result<Option<f64>, AppError> {
+        if !self.players.iter().any(|p| p.id == player_id) {
+            return Err(AppError::PlayerNotFound(player_id));
         }
 
+        if let Some(sample) = sample {
+            let round_trip = sample.client_receive - sample.client_send;
+            if !round_trip.is_finite() || round_trip < 0.0 {
+                return Err(AppError::invalid_parameter("sample", "client_receive 不能早于 client_send"));
+            }
+            // server_time 必须是服务器之前返回的时刻
+            if !sample.server_time.is_finite() || sample.server_time > server_clock_ms(received_at) {
+                return Err(AppError::invalid_parameter("sample", "server_time 不是服务器返回的时间"));
+            }
+
+            let offset = sample.server_time - (sample.client_send + sample.client_receive) / 2.0;
+            if self.clock_round_trips.get(&player_id).is_none_or(|&best| round_trip < best) {
+                self.clock_round_trips.insert(player_id, round_trip);
+                self.clock_offsets.insert(player_id, offset);
+            }
+        }
+        Ok(self.clock_offsets.get(&player_id).copied())
+    }
+
+    // 由服务器计算反应时间：优先使用经时钟偏移校正的客户端按键时间戳，
+    // 否则以服务器收到请求的时间为准。校正后的按键时间最多比收到请求早一次单程延迟
+    // （对时测得的往返时间的一半）加上 clock_tolerance_ms 的抖动余量
+    pub fn record_reaction(
+        &mut self,
+        player_id: u8,
+        client_timestamp: Option<f64>,
+        received_at: Instant,
+    ) -> Result<PlayerRoundResult, AppError> {
+        self.expect_state(GameState::Racing)?;
+
         // 检查玩家是否存在
         if !self.players.iter().any(|p| p.id == player_id) {
-            return Err(format!("玩家ID {} 不存在，有效玩家ID: {:?}", 
-                player_id, self.players.iter().map(|p| p.id).collect::<Vec<_>>()));
+            return Err(AppError::PlayerNotFound(player_id));
         }
 
         if self.reacted_players.contains(&player_id) {
-            return Err(format!("玩家 {} 已反应，已反应玩家: {:?}", player_id, self.reacted_players));
+            return Err(AppError::AlreadyReacted(player_id));
         }
 
-        // 检查是否抢跑（反应时间小于100ms）
-        let is_false_start = reaction_time < 100.0;
+        let green_light_time = self.green_light_time.ok_or_else(|| AppError::Rejected("绿灯尚未亮起".to_string()))?;
+        let green_ms = server_clock_ms(green_light_time);
+        let received_ms = server_clock_ms(received_at);
+
+        let sync = self.clock_offsets.get(&player_id).zip(self.clock_round_trips.get(&player_id));
+        let reaction_time = match (client_timestamp, sync) {
+            (Some(timestamp), Some((offset, round_trip))) => {
+                let pressed_ms = timestamp + offset;
+                let max_transit = round_trip / 2.0 + self.timing.clock_tolerance_ms;
+                let transit = received_ms - pressed_ms;
+                if !transit.is_finite() || transit > max_transit {
+                    return Err(AppError::Rejected(format!(
+                        "按键时间戳比服务器收到请求早 {:.0}ms，超过允许的 {:.0}ms",
+                        transit, max_transit
+                    )));
+                }
+                // 晚于收到请求的按键时间不可能是真实的，按收到请求的时刻计算
+                pressed_ms.min(received_ms) - green_ms
+            }
+            _ => received_ms - green_ms,
+        };
+
+        // 检查是否抢跑（反应时间小于阈值）
+        let is_false_start = reaction_time < self.timing.false_start_threshold_ms;
 
         self.reacted_players.push(player_id);
         self.player_reactions.insert(player_id, reaction_time);
@@ -141,67 +406,41 @@ impl RacingGame {
         })
     }
 
-    pub fn finish_round(&mut self) -> RoundResult {
-        self.game_state = GameState::Finished;
+    // 玩家在所有回合中的最佳有效反应时间
+    pub fn best_reaction_time(&self, player_id: u8) -> Option<f64> {
+        self.round_results
+            .iter()
+            .flat_map(|round| &round.player_results)
+            .filter(|r| r.player_id == player_id && !r.is_false_start)
+            .filter_map(|r| r.reaction_time)
+            .min_by(|a, b| a.total_cmp(b))
+    }
+
+    pub fn finish_round(&mut self) -> Result<RoundResult, AppError> {
+        self.transition(GameState::Finished)?;
         self.current_round += 1;
 
+        let threshold = self.timing.false_start_threshold_ms;
         let mut player_results: Vec<PlayerRoundResult> = self.players
             .iter()
             .map(|player| {
-                // 检查玩家是否有反应记录
-                let _has_reacted = self.reacted_players.contains(&player.id);
                 let reaction_time = self.player_reactions.get(&player.id).copied();
                 
-                // 检查是否抢跑（反应时间小于100ms）
-                let is_false_start = reaction_time.map(|t| t < 100.0).unwrap_or(false);
-                
-                // 计算基础积分
-                let points = if is_false_start {
-                    -5  // 抢跑扣5分
-                } else {
-                    match reaction_time {
-                        Some(time) => {
-                            if time < 200.0 { 15 }      // 极快反应
-                            else if time < 300.0 { 12 } // 快速反应
-                            else if time < 400.0 { 10 } // 正常反应
-                            else if time < 500.0 { 8 }  // 较慢反应
-                            else { 5 }                  // 最慢反应
-                        }
-                        None => 0  // 未反应得0分
-                    }
-                };
+                // 检查是否抢跑（反应时间小于阈值）
+                let is_false_start = reaction_time.map(|t| t < threshold).unwrap_or(false);
 
                 PlayerRoundResult {
                     player_id: player.id,
                     reaction_time: if is_false_start { None } else { reaction_time },
                     is_false_start,
-                    rank: None,  // 将在后面计算
-                    points,
+                    rank: None,  // 由积分规则计算
+                    points: 0,
                 }
             })
             .collect();
 
-        // 计算排名 - 只对有效反应的玩家排序
-        let mut valid_results: Vec<&mut PlayerRoundResult> = player_results
-            .iter_mut()
-            .filter(|r| !r.is_false_start && r.reaction_time.is_some())
-            .collect();
-
-        valid_results.sort_by(|a, b| {
-            a.reaction_time.partial_cmp(&b.reaction_time).unwrap()
-        });
-
-        // 为有效反应的玩家设置排名和额外积分
-        for (index, result) in valid_results.iter_mut().enumerate() {
-            result.rank = Some((index + 1) as u8);
-            // 根据排名给额外奖励积分
-            result.points += match index {
-                0 => 10,  // 第1名额外+10
-                1 => 7,   // 第2名额外+7
-                2 => 5,   // 第3名额外+5
-                _ => 3,   // 其他名次额外+3
-            };
-        }
+        // 按本局的积分规则计算排名和积分
+        self.scoring.score_round(&mut player_results, &self.round_results);
 
         // 更新玩家总积分
         for result in &player_results {
@@ -219,21 +458,107 @@ impl RacingGame {
 
         // 检查游戏是否结束
         if self.current_round >= self.max_rounds {
-            self.game_state = GameState::GameOver;
+            self.transition(GameState::GameOver)?;
         } else {
-            self.game_state = GameState::Waiting;
+            self.transition(GameState::Waiting)?;
         }
 
-        round_result
+        Ok(round_result)
     }
 }
 
 // API请求结构
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateGameRequest {
-    pub player_count: u8,
-    pub round_count: u8,
+    // 按较宽的整数读取，超出范围的值由 validate 报告为字段错误，而不是 JSON 解析失败
+    pub player_count: i64,
+    pub round_count: i64,
     pub player_names: Vec<String>,
+    #[serde(default)]
+    pub scoring: Option<ScoringConfig>, // 预设名称（classic/spec）或自定义规则，默认 classic
+    #[serde(default)]
+    pub auto_start: bool,
+}
+
+impl Default for CreateGameRequest {
+    fn default() -> Self {
+        Self {
+            player_count: 2,
+            round_count: 3,
+            player_names: vec!["玩家1".to_string(), "玩家2".to_string()],
+            scoring: None,
+            auto_start: false,
+        }
+    }
+}
+
+impl CreateGameRequest {
+    // 去掉首尾空白后的玩家名称，未提供名称的玩家使用默认名称；需先通过 validate 的人数检查
+    pub fn resolved_names(&self) -> Vec<String> {
+        (1..=self.player_count)
+            .map(|id| match self.player_names.get((id - 1) as usize) {
+                Some(name) => name.trim().to_string(),
+                None => format!("玩家{}", id),
+            })
+            .collect()
+    }
+
+    // 按配置的上下限校验，一次返回所有出错的字段
+    pub fn validate(&self, limits: &RacingConfig) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+        let player_count_valid = (i64::from(limits.min_players)..=i64::from(limits.max_players)).contains(&self.player_count);
+        if !player_count_valid {
+            errors.push(FieldError::new(
+                "player_count",
+                format!("须在 {} 到 {} 之间", limits.min_players, limits.max_players),
+            ));
+        }
+        if !(i64::from(limits.min_rounds)..=i64::from(limits.max_rounds)).contains(&self.round_count) {
+            errors.push(FieldError::new(
+                "round_count",
+                format!("须在 {} 到 {} 之间", limits.min_rounds, limits.max_rounds),
+            ));
+        }
+        if self.player_names.len() as i64 > self.player_count {
+            errors.push(FieldError::new(
+                "player_names",
+                format!("提供了 {} 个名称，超过玩家数量 {}", self.player_names.len(), self.player_count),
+            ));
+        }
+
+        for (index, name) in self.player_names.iter().enumerate() {
+            let name = name.trim();
+            let length = name.chars().count();
+            if length == 0 {
+                errors.push(FieldError::new(format!("player_names[{}]", index), "名称不能为空"));
+            } else if length > limits.max_name_length {
+                errors.push(FieldError::new(
+                    format!("player_names[{}]", index),
+                    format!("名称不能超过 {} 个字符", limits.max_name_length),
+                ));
+            }
+        }
+
+        // 默认名称也参与比较，避免出现两个“玩家2”；人数不合法时无法确定默认名称，跳过
+        let names = if player_count_valid { self.resolved_names() } else { Vec::new() };
+        let mut first_index: HashMap<&str, usize> = HashMap::new();
+        for (index, name) in names.iter().enumerate().filter(|(_, name)| !name.is_empty()) {
+            if let Some(&first) = first_index.get(name.as_str()) {
+                errors.push(FieldError::new(
+                    format!("player_names[{}]", first),
+                    format!("与玩家{}的名称重复: {}", index + 1, name),
+                ));
+            } else {
+                first_index.insert(name, index);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(errors))
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -244,113 +569,455 @@ pub struct GameResponse {
     pub current_round: u8,
     pub max_rounds: u8,
     pub round_results: Vec<RoundResult>,
+    pub scoring: ScoringRules,
+    pub auto_start: bool,
+    pub red_lights: u8,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReactionRequest {
     pub game_id: String,
     pub player_id: u8,
-    pub reaction_time: f64,
+    #[serde(default)]
+    pub client_timestamp: Option<f64>, // 客户端按键时刻（客户端时钟，毫秒）
+}
+
+fn default_ready() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadyRequest {
+    pub game_id: String,
+    pub player_id: u8,
+    #[serde(default = "default_ready")]
+    pub ready: bool, // false 表示取消准备
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClockSyncRequest {
+    pub game_id: String,
+    pub player_id: u8,
+    #[serde(default)]
+    pub sample: Option<ClockSample>, // 上一次对时的往返记录，第一次对时时省略
+}
+
+// 一次对时往返：客户端发送时刻 t0、服务器返回的时刻 t1、客户端收到响应的时刻 t2
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClockSample {
+    pub client_send: f64,
+    pub server_time: f64,
+    pub client_receive: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClockSyncResponse {
+    pub server_time: f64,
+    pub offset: Option<f64>, // 目前采用的偏移，还没有完整往返时为 null
 }
 
 // 全局游戏存储
 pub struct GameStateStorage {
     games: Arc<Mutex<HashMap<String, RacingGame>>>,
+    expired: Arc<Mutex<HashMap<String, Instant>>>, // 已清理的游戏ID及清理时刻
+    events: Arc<EventHub>,
+    database: Option<Arc<dyn GameDatabase + Send + Sync>>,
+    config: RacingConfig,
+    snapshot_lock: Mutex<()>, // 定期快照和退出时的快照写同一个临时文件，不能同时进行
+}
+
+impl Default for GameStateStorage {
+    fn default() -> Self {
+        Self::new(RacingConfig::default())
+    }
+}
+
+// 比较游戏前后状态，向订阅者推送状态和信号灯变化
+fn publish_transition(events: &EventHub, game: &RacingGame, before: (GameState, u8)) {
+    let (state_before, lights_before) = before;
+    if game.game_state == state_before && game.red_lights == lights_before {
+        return;
+    }
+
+    if matches!(game.game_state, GameState::Countdown | GameState::Ready | GameState::Racing) {
+        events.publish(&game.game_id, &RacingEvent::LightChange {
+            game_state: game.game_state.clone(),
+            red_lights: game.red_lights,
+        });
+    }
+    if game.game_state != state_before {
+        events.publish(&game.game_id, &RacingEvent::StateChange { game: game.to_response() });
+    }
 }
 
 impl GameStateStorage {
-    pub fn new() -> Self {
+    pub fn new(config: RacingConfig) -> Self {
         Self {
             games: Arc::new(Mutex::new(HashMap::new())),
+            expired: Arc::new(Mutex::new(HashMap::new())),
+            events: Arc::new(EventHub::new()),
+            database: None,
+            config,
+            snapshot_lock: Mutex::new(()),
         }
     }
 
-    pub fn create_game(&self, req: CreateGameRequest) -> GameResponse {
-        let mut games = self.games.lock().unwrap();
-        let game = RacingGame::new(req.player_count, req.round_count, req.player_names);
-        let game_id = game.game_id.clone();
-        let response = GameResponse {
-            game_id: game_id.clone(),
-            game_state: game.game_state.clone(),
-            players: game.players.clone(),
-            current_round: game.current_round,
-            max_rounds: game.max_rounds,
-            round_results: game.round_results.clone(),
-        };
-        games.insert(game_id, game);
-        response
-    }
-
-    pub fn start_game(&self, game_id: &str) -> Option<GameResponse> {
-        let mut games = self.games.lock().unwrap();
-        games.get_mut(game_id).map(|game| {
-            game.start_round();
-            GameResponse {
-                game_id: game_id.to_string(),
-                game_state: game.game_state.clone(),
-                players: game.players.clone(),
-                current_round: game.current_round,
-                max_rounds: game.max_rounds,
-                round_results: game.round_results.clone(),
+    // 游戏结束时自动把每位玩家的成绩写入数据库
+    pub fn with_database(database: Arc<dyn GameDatabase + Send + Sync>, config: RacingConfig) -> Self {
+        Self {
+            database: Some(database),
+            ..Self::new(config)
+        }
+    }
+
+    pub fn config(&self) -> &RacingConfig {
+        &self.config
+    }
+
+    pub fn events(&self) -> Arc<EventHub> {
+        Arc::clone(&self.events)
+    }
+
+    // 启动信号灯调度线程，由服务器驱动所有游戏的倒计时和绿灯
+    pub fn start_light_scheduler(&self) -> thread::JoinHandle<()> {
+        let games = Arc::clone(&self.games);
+        let events = Arc::clone(&self.events);
+        thread::spawn(move || loop {
+            // 单次调度出错不能让信号灯线程退出
+            let tick = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut games = lock_or_recover(&games);
+                let now = Instant::now();
+                for game in games.values_mut() {
+                    let before = (game.game_state.clone(), game.red_lights);
+                    game.advance_lights(now);
+                    publish_transition(&events, game, before);
+                }
+            }));
+            if tick.is_err() {
+                println!("⚠️ 信号灯调度出错，继续运行");
             }
+            thread::sleep(SCHEDULER_TICK);
         })
     }
 
-    pub fn record_reaction(&self, game_id: &str, player_id: u8, reaction_time: f64) -> Result<PlayerRoundResult, String> {
-        let mut games = self.games.lock().unwrap();
-        if let Some(game) = games.get_mut(game_id) {
-            game.record_reaction(player_id, reaction_time)
+    pub fn create_game(&self, req: CreateGameRequest) -> Result<GameResponse, AppError> {
+        req.validate(&self.config)?;
+
+        let mut games = lock_or_recover(&self.games);
+        if games.len() >= self.config.max_games {
+            // 达到上限时先腾出已结束的游戏，仍然不够再拒绝
+            let finished: Vec<String> = games
+                .values()
+                .filter(|game| game.game_state == GameState::GameOver)
+                .map(|game| game.game_id.clone())
+                .collect();
+            let mut expired = lock_or_recover(&self.expired);
+            for game_id in finished {
+                games.remove(&game_id);
+                self.events.close(&game_id);
+                expired.insert(game_id, Instant::now());
+            }
+        }
+        if games.len() >= self.config.max_games {
+            return Err(AppError::TooManyGames(self.config.max_games));
+        }
+        let player_names = req.resolved_names();
+        let scoring = req.scoring.map(ScoringConfig::into_rules).unwrap_or_default();
+        // 通过校验后人数和回合数都在配置的 u8 范围内
+        let game = RacingGame::new(
+            req.player_count as u8,
+            req.round_count as u8,
+            player_names,
+            scoring,
+            req.auto_start,
+            self.config.timing(),
+        );
+        let response = game.to_response();
+        games.insert(game.game_id.clone(), game);
+        Ok(response)
+    }
+
+    pub fn start_game(&self, game_id: &str) -> Result<GameResponse, AppError> {
+        let mut games = lock_or_recover(&self.games);
+        let game = self.active_game(&mut games, game_id)?;
+        let before = (game.game_state.clone(), game.red_lights);
+        game.start_round()?;
+        publish_transition(&self.events, game, before);
+        Ok(game.to_response())
+    }
+
+    pub fn set_ready(&self, req: &ReadyRequest) -> Result<GameResponse, AppError> {
+        let mut games = lock_or_recover(&self.games);
+        let game = self.active_game(&mut games, &req.game_id)?;
+        let before = (game.game_state.clone(), game.red_lights);
+        game.set_ready(req.player_id, req.ready)?;
+
+        if game.auto_start && game.all_ready() {
+            game.start_round()?;
+            publish_transition(&self.events, game, before);
         } else {
-            Err("游戏未找到".to_string())
+            self.events.publish(&req.game_id, &RacingEvent::StateChange { game: game.to_response() });
+        }
+        Ok(game.to_response())
+    }
+
+    pub fn sync_clock(&self, req: &ClockSyncRequest, received_at: Instant) -> Result<ClockSyncResponse, AppError> {
+        let mut games = lock_or_recover(&self.games);
+        let game = self.active_game(&mut games, &req.game_id)?;
+        let offset = game.sync_clock(req.player_id, req.sample.as_ref(), received_at)?;
+        Ok(ClockSyncResponse {
+            server_time: server_clock_ms(received_at),
+            offset,
+        })
+    }
+
+    pub fn record_reaction(&self, req: &ReactionRequest, received_at: Instant) -> Result<PlayerRoundResult, AppError> {
+        let mut games = lock_or_recover(&self.games);
+        let game = self.active_game(&mut games, &req.game_id)?;
+        let result = game.record_reaction(req.player_id, req.client_timestamp, received_at)?;
+        self.events.publish(&req.game_id, &RacingEvent::PlayerResult { result: result.clone() });
+        Ok(result)
+    }
+
+    pub fn finish_round(&self, game_id: &str) -> Result<RoundResult, AppError> {
+        let (result, finished_game) = {
+            let mut games = lock_or_recover(&self.games);
+            let game = self.active_game(&mut games, game_id)?;
+            let before = (game.game_state.clone(), game.red_lights);
+            let result = game.finish_round()?;
+            self.events.publish(game_id, &RacingEvent::RoundResult { result: result.clone() });
+            publish_transition(&self.events, game, before.clone());
+
+            let just_finished = before.0 != GameState::GameOver && game.game_state == GameState::GameOver;
+            (result, just_finished.then(|| game.clone()))
+        };
+
+        // 在释放游戏锁之后写库，避免阻塞信号灯调度
+        if let Some(game) = finished_game {
+            self.save_results(&game);
+        }
+        Ok(result)
+    }
+
+    fn save_results(&self, game: &RacingGame) {
+        let Some(database) = &self.database else {
+            return;
+        };
+
+        for player in &game.players {
+            let record = NewGameRecord {
+                game_type: GameType::Racing,
+                game_id: game.game_id.clone(),
+                player_name: player.name.clone(),
+                score: player.score,
+                reaction_time: game.best_reaction_time(player.id),
+                attempts: None,
+                duration_ms: None,
+                difficulty: None,
+            };
+            if let Err(e) = database.save_game_record(record) {
+                println!("保存游戏记录失败 {} {}: {}", game.game_id, player.name, e);
+            }
+        }
+    }
+
+    pub fn get_game(&self, game_id: &str) -> Result<GameResponse, AppError> {
+        let games = lock_or_recover(&self.games);
+        games
+            .get(game_id)
+            .map(|game| game.to_response())
+            .ok_or_else(|| self.missing_game(game_id))
+    }
+
+    // 把所有游戏写入快照文件；复制后立即释放锁，写文件不阻塞游戏
+    pub fn save_snapshot(&self, path: &Path) -> std::io::Result<usize> {
+        let _saving = lock_or_recover(&self.snapshot_lock);
+        let games: Vec<RacingGame> = lock_or_recover(&self.games).values().cloned().collect();
+        let count = games.len();
+        snapshot::save(path, games)?;
+        Ok(count)
+    }
+
+    // 启动时从快照恢复游戏，中断的回合回到等待状态
+    pub fn restore_snapshot(&self, path: &Path) -> std::io::Result<usize> {
+        let restored = snapshot::load(path)?;
+        let count = restored.len();
+        let mut games = lock_or_recover(&self.games);
+        for mut game in restored {
+            game.rollback_interrupted_round();
+            games.insert(game.game_id.clone(), game);
         }
+        Ok(count)
     }
 
-    pub fn trigger_green_light(&self, game_id: &str) -> Option<GameResponse> {
-        let mut games = self.games.lock().unwrap();
-        games.get_mut(game_id).map(|game| {
-            game.trigger_green_light();
-            GameResponse {
-                game_id: game_id.to_string(),
-                game_state: game.game_state.clone(),
-                players: game.players.clone(),
-                current_round: game.current_round,
-                max_rounds: game.max_rounds,
-                round_results: game.round_results.clone(),
+    // 启动定期保存快照的线程
+    pub fn start_snapshotter(self: &Arc<Self>, path: PathBuf, interval: Duration) -> thread::JoinHandle<()> {
+        let storage = Arc::clone(self);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if let Err(e) = storage.save_snapshot(&path) {
+                println!("⚠️ 保存游戏快照失败 {}: {}", path.display(), e);
             }
         })
     }
 
-    pub fn finish_round(&self, game_id: &str) -> Option<RoundResult> {
-        let mut games = self.games.lock().unwrap();
-        games.get_mut(game_id).map(|game| game.finish_round())
+    // 查找游戏并记录玩家活动时间
+    fn active_game<'a>(&self, games: &'a mut HashMap<String, RacingGame>, game_id: &str) -> Result<&'a mut RacingGame, AppError> {
+        let game = games.get_mut(game_id).ok_or_else(|| self.missing_game(game_id))?;
+        game.last_activity = Instant::now();
+        Ok(game)
     }
 
-    pub fn get_game(&self, game_id: &str) -> Option<GameResponse> {
-        let games = self.games.lock().unwrap();
-        games.get(game_id).map(|game| GameResponse {
-            game_id: game_id.to_string(),
-            game_state: game.game_state.clone(),
-            players: game.players.clone(),
-            current_round: game.current_round,
-            max_rounds: game.max_rounds,
-            round_results: game.round_results.clone(),
+    // 区分从未存在的游戏和因超时已被清理的游戏
+    fn missing_game(&self, game_id: &str) -> AppError {
+        if lock_or_recover(&self.expired).contains_key(game_id) {
+            AppError::GameExpired(game_id.to_string())
+        } else {
+            AppError::GameNotFound(game_id.to_string())
+        }
+    }
+
+    // 启动清理线程，定期移除空闲超时的游戏（包括已结束的游戏）
+    pub fn start_sweeper(&self) -> thread::JoinHandle<()> {
+        let games = Arc::clone(&self.games);
+        let expired = Arc::clone(&self.expired);
+        let events = Arc::clone(&self.events);
+        let timeout = self.config.game_timeout();
+        let interval = SWEEP_INTERVAL.min(timeout);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let sweep = panic::catch_unwind(AssertUnwindSafe(|| {
+                let now = Instant::now();
+                let evicted: Vec<String> = {
+                    let mut games = lock_or_recover(&games);
+                    let idle: Vec<String> = games
+                        .values()
+                        .filter(|game| now.duration_since(game.last_activity) >= timeout)
+                        .map(|game| game.game_id.clone())
+                        .collect();
+                    for game_id in &idle {
+                        games.remove(game_id);
+                    }
+                    idle
+                };
+                if !evicted.is_empty() {
+                    println!("🧹 清理了 {} 个超时的游戏", evicted.len());
+                }
+
+                let mut expired = lock_or_recover(&expired);
+                expired.retain(|_, evicted_at| now.duration_since(*evicted_at) < EXPIRED_GAME_RETENTION);
+                for game_id in evicted {
+                    events.publish(&game_id, &RacingEvent::GameExpired { game_id: game_id.clone() });
+                    events.close(&game_id);
+                    expired.insert(game_id, now);
+                }
+            }));
+            if sweep.is_err() {
+                println!("⚠️ 清理过期游戏出错，继续运行");
+            }
         })
     }
 }
 
 
 
+// 猜数字游戏状态：Playing 时可以猜测，猜中进入 Won，用完次数进入 Lost，
+// 结束后只能通过重置开始新的一局
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GuessStatus {
+    Playing,
+    Won,
+    Lost,
+}
+
+// 自定义难度允许的最大数字和最多次数
+const CUSTOM_MAX_NUMBER: u32 = 1_000_000;
+const CUSTOM_MAX_ATTEMPTS: u32 = 100;
+
+// 猜数字难度，创建会话时选择；不同难度的成绩分开排行
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Expert,
+    Custom,
+}
+
+impl Difficulty {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+            Difficulty::Expert => "expert",
+            Difficulty::Custom => "custom",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            "expert" => Some(Difficulty::Expert),
+            "custom" => Some(Difficulty::Custom),
+            _ => None,
+        }
+    }
+
+    // 预设难度的范围和次数，normal 沿用配置文件中的 [guess]；custom 没有预设
+    fn preset(&self, normal: GuessSettings) -> Option<GuessSettings> {
+        let (min, max, max_attempts) = match self {
+            Difficulty::Easy => (1, 50, 10),
+            Difficulty::Normal => return Some(normal),
+            Difficulty::Hard => (1, 1000, 10),
+            Difficulty::Expert => (1, 10000, 14),
+            Difficulty::Custom => return None,
+        };
+        Some(GuessSettings { min, max, max_attempts })
+    }
+}
+
+// 一局猜数字的数字范围（含两端）和最多次数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuessSettings {
+    pub min: u32,
+    pub max: u32,
+    pub max_attempts: u32,
+}
+
 // 猜数字游戏相关结构
 #[derive(Debug, Serialize, Deserialize)]
 struct GuessResponse {
     message: String,
     attempts: u32,
+    remaining_attempts: u32,
     correct: bool,
-    range: (u32, u32),
+    status: GuessStatus,
+    range: (u32, u32), // 答案所在的范围（含两端）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer: Option<u32>, // 游戏结束后公布答案
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct GameInfo {
+    difficulty: Difficulty,
+    range: (u32, u32),
+    max_attempts: u32,
+    attempts: u32,
+    status: GuessStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GuessSessionResponse {
+    session_id: String,
+    difficulty: Difficulty,
     range: (u32, u32),
     max_attempts: u32,
 }
@@ -365,65 +1032,318 @@ struct LeaderboardEntry {
     name: String,
     score: u32,
     attempts: u32,
+    duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    difficulty: Option<Difficulty>,
     date: String,
 }
 
-// 猜数字游戏状态
+impl From<GameRecord> for LeaderboardEntry {
+    fn from(record: GameRecord) -> Self {
+        Self {
+            name: record.player_name,
+            score: record.score.max(0) as u32,
+            attempts: record.attempts.unwrap_or_default(),
+            duration_ms: record.duration_ms.unwrap_or_default(),
+            difficulty: record.difficulty,
+            date: record.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CreateSessionRequest {
+    #[serde(default)]
+    player_name: Option<String>,
+    #[serde(default)]
+    difficulty: Difficulty,
+    // 以下三项只在 custom 难度下使用
+    #[serde(default)]
+    min: Option<u32>,
+    #[serde(default)]
+    max: Option<u32>,
+    #[serde(default)]
+    max_attempts: Option<u32>,
+}
+
+impl CreateSessionRequest {
+    // 解析本局的数字范围和次数，custom 难度的参数有问题时返回所有字段错误
+    fn settings(&self, normal: GuessSettings) -> Result<GuessSettings, AppError> {
+        let custom = [("min", self.min), ("max", self.max), ("max_attempts", self.max_attempts)];
+        if let Some(settings) = self.difficulty.preset(normal) {
+            let errors: Vec<FieldError> = custom
+                .iter()
+                .filter(|(_, value)| value.is_some())
+                .map(|(field, _)| FieldError::new(*field, "只有 custom 难度可以指定"))
+                .collect();
+            return if errors.is_empty() { Ok(settings) } else { Err(AppError::Validation(errors)) };
+        }
+
+        let mut errors: Vec<FieldError> = custom
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .map(|(field, _)| FieldError::new(*field, "custom 难度必须指定"))
+            .collect();
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if max > CUSTOM_MAX_NUMBER {
+                errors.push(FieldError::new("max", format!("不能超过 {}", CUSTOM_MAX_NUMBER)));
+            }
+            if min >= max {
+                errors.push(FieldError::new("min", format!("必须小于 max ({})", max)));
+            }
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            if !(1..=CUSTOM_MAX_ATTEMPTS).contains(&max_attempts) {
+                errors.push(FieldError::new("max_attempts", format!("须在 1 到 {} 之间", CUSTOM_MAX_ATTEMPTS)));
+            }
+        }
+        match (self.min, self.max, self.max_attempts) {
+            (Some(min), Some(max), Some(max_attempts)) if errors.is_empty() => Ok(GuessSettings { min, max, max_attempts }),
+            _ => Err(AppError::Validation(errors)),
+        }
+    }
+}
+
+// 一局已完成的猜数字游戏
+#[derive(Debug)]
+struct CompletedGuessGame {
+    player_name: String,
+    difficulty: Difficulty,
+    attempts: u32,
+    max_attempts: u32,
+    duration: Duration,
+}
+
+impl CompletedGuessGame {
+    // 尝试次数越少得分越高：第1次猜中得满分，用满次数得最低分
+    fn score(&self) -> u32 {
+        (self.max_attempts + 1).saturating_sub(self.attempts) * 10
+    }
+}
+
+// 猜数字游戏状态，min_number 和 max_number 是答案可能所在的范围（含两端），
+// settings 是本局难度对应的完整范围和次数
 struct GuessGameState {
+    player_name: String,
+    difficulty: Difficulty,
+    settings: GuessSettings,
     target_number: u32,
     attempts: u32,
     max_attempts: u32,
     min_number: u32,
     max_number: u32,
+    status: GuessStatus,
+    started_at: Instant,
+    last_activity: Instant, // 空闲超时后会话被清理
 }
 
 impl GuessGameState {
-    fn new() -> Self {
+    fn new(player_name: String, difficulty: Difficulty, settings: GuessSettings) -> Self {
         let mut rng = rand::thread_rng();
         Self {
-            target_number: rng.gen_range(1..=100),
+            player_name,
+            difficulty,
+            target_number: rng.gen_range(settings.min..=settings.max),
             attempts: 0,
-            max_attempts: 10,
-            min_number: 1,
-            max_number: 100,
+            max_attempts: settings.max_attempts,
+            min_number: settings.min,
+            max_number: settings.max,
+            settings,
+            status: GuessStatus::Playing,
+            started_at: Instant::now(),
+            last_activity: Instant::now(),
         }
     }
 
+    // 开始新的一局：保持难度，重新选数，恢复完整范围和次数
     fn reset(&mut self) {
-        let mut rng = rand::thread_rng();
-        self.target_number = rng.gen_range(1..=100);
-        self.attempts = 0;
+        *self = Self::new(std::mem::take(&mut self.player_name), self.difficulty, self.settings);
+    }
+
+    // 游戏结束后才公布答案
+    fn answer(&self) -> Option<u32> {
+        (self.status != GuessStatus::Playing).then_some(self.target_number)
+    }
+
+    fn info(&self) -> GameInfo {
+        GameInfo {
+            difficulty: self.difficulty,
+            range: (self.min_number, self.max_number),
+            max_attempts: self.max_attempts,
+            attempts: self.attempts,
+            status: self.status,
+            answer: self.answer(),
+        }
     }
 
-    fn guess(&mut self, number: u32) -> GuessResponse {
+    // 猜中时记下本局成绩
+    fn complete(&self) -> CompletedGuessGame {
+        CompletedGuessGame {
+            player_name: self.player_name.clone(),
+            difficulty: self.difficulty,
+            attempts: self.attempts,
+            max_attempts: self.max_attempts,
+            duration: self.started_at.elapsed(),
+        }
+    }
+
+    fn guess(&mut self, number: u32) -> Result<(GuessResponse, Option<CompletedGuessGame>), AppError> {
+        if self.status != GuessStatus::Playing {
+            return Err(StateError::GuessFinished { status: self.status }.into());
+        }
+        // 超出本局数字范围的猜测不计入次数
+        if !(self.settings.min..=self.settings.max).contains(&number) {
+            return Err(AppError::invalid_parameter(
+                "number",
+                format!("须在 {} 到 {} 之间", self.settings.min, self.settings.max),
+            ));
+        }
+
         self.attempts += 1;
-        
-        if number == self.target_number {
-            let response = GuessResponse {
-                message: format!("🎉 恭喜你！数字就是 {}！", self.target_number),
-                attempts: self.attempts,
-                correct: true,
-                range: (self.min_number, self.max_number),
+        let remaining = self.max_attempts.saturating_sub(self.attempts);
+
+        let (message, completed) = if number == self.target_number {
+            self.status = GuessStatus::Won;
+            (format!("🎉 恭喜你！数字就是 {}！", self.target_number), Some(self.complete()))
+        } else {
+            let hint = if number < self.target_number {
+                self.min_number = self.min_number.max(number + 1);
+                "太小了"
+            } else {
+                self.max_number = self.max_number.min(number - 1);
+                "太大了"
             };
-            self.reset();
-            return response;
-        } else if number < self.target_number {
-            self.min_number = number.max(self.min_number);
-            GuessResponse {
-                message: format!("太小了！试试 {} 到 {} 之间的数字", self.min_number + 1, self.max_number),
-                attempts: self.attempts,
-                correct: false,
-                range: (self.min_number, self.max_number),
+            if remaining == 0 {
+                self.status = GuessStatus::Lost;
+                (format!("{}！次数已用完，答案是 {}", hint, self.target_number), None)
+            } else {
+                (
+                    format!("{}！试试 {} 到 {} 之间的数字，还剩 {} 次", hint, self.min_number, self.max_number, remaining),
+                    None,
+                )
             }
-        } else {
-            self.max_number = number.min(self.max_number);
-            GuessResponse {
-                message: format!("太大了！试试 {} 到 {} 之间的数字", self.min_number, self.max_number - 1),
-                attempts: self.attempts,
-                correct: false,
-                range: (self.min_number, self.max_number),
+        };
+
+        let response = GuessResponse {
+            message,
+            attempts: self.attempts,
+            remaining_attempts: remaining,
+            correct: self.status == GuessStatus::Won,
+            status: self.status,
+            range: (self.min_number, self.max_number),
+            answer: self.answer(),
+        };
+        Ok((response, completed))
+    }
+}
+
+// 猜数字游戏会话存储，每位访客拥有独立的游戏
+struct GuessSessionStorage {
+    sessions: Arc<Mutex<HashMap<String, GuessGameState>>>,
+    database: Arc<dyn GameDatabase + Send + Sync>,
+    config: GuessConfig,
+}
+
+impl GuessSessionStorage {
+    fn new(database: Arc<dyn GameDatabase + Send + Sync>, config: GuessConfig) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            database,
+            config,
+        }
+    }
+
+    fn create_session(&self, req: CreateSessionRequest) -> Result<GuessSessionResponse, AppError> {
+        let settings = req.settings(self.config.settings())?;
+        let mut sessions = lock_or_recover(&self.sessions);
+        if sessions.len() >= self.config.max_sessions {
+            // 达到上限时先腾出已结束的会话，仍然不够再拒绝
+            sessions.retain(|_, state| state.status == GuessStatus::Playing);
+        }
+        if sessions.len() >= self.config.max_sessions {
+            return Err(AppError::TooManySessions(self.config.max_sessions));
+        }
+        let session_id = format!("session_{}", rand::thread_rng().gen::<u64>());
+        let player_name = req
+            .player_name
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "匿名玩家".to_string());
+        let state = GuessGameState::new(player_name, req.difficulty, settings);
+        let response = GuessSessionResponse {
+            session_id: session_id.clone(),
+            difficulty: state.difficulty,
+            range: (state.min_number, state.max_number),
+            max_attempts: state.max_attempts,
+        };
+        sessions.insert(session_id, state);
+        Ok(response)
+    }
+
+    // 取出会话并记录一次活动
+    fn active_session<'a>(
+        sessions: &'a mut HashMap<String, GuessGameState>,
+        session_id: &str,
+    ) -> Result<&'a mut GuessGameState, AppError> {
+        let state = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| AppError::SessionNotFound(session_id.to_string()))?;
+        state.last_activity = Instant::now();
+        Ok(state)
+    }
+
+    fn get_info(&self, session_id: &str) -> Result<GameInfo, AppError> {
+        let mut sessions = lock_or_recover(&self.sessions);
+        Ok(Self::active_session(&mut sessions, session_id)?.info())
+    }
+
+    fn guess(&self, session_id: &str, number: u32) -> Result<GuessResponse, AppError> {
+        let (response, completed) = {
+            let mut sessions = lock_or_recover(&self.sessions);
+            Self::active_session(&mut sessions, session_id)?.guess(number)?
+        };
+
+        // 自定义难度的范围和次数由玩家决定，成绩无法与他人比较，不计入排行榜
+        if let Some(game) = completed.filter(|game| game.difficulty != Difficulty::Custom) {
+            let record = NewGameRecord {
+                game_type: GameType::GuessNumber,
+                game_id: session_id.to_string(),
+                player_name: game.player_name.clone(),
+                score: game.score() as i32,
+                reaction_time: None,
+                attempts: Some(game.attempts),
+                duration_ms: Some(game.duration.as_millis() as u64),
+                difficulty: Some(game.difficulty),
+            };
+            if let Err(e) = self.database.save_game_record(record) {
+                println!("保存猜数字成绩失败 {}: {}", session_id, e);
             }
         }
+        Ok(response)
+    }
+
+    fn reset(&self, session_id: &str) -> Result<GameInfo, AppError> {
+        let mut sessions = lock_or_recover(&self.sessions);
+        let state = Self::active_session(&mut sessions, session_id)?;
+        state.reset();
+        Ok(state.info())
+    }
+
+    // 启动清理线程，定期移除空闲超时的会话
+    fn start_sweeper(&self) -> thread::JoinHandle<()> {
+        let sessions = Arc::clone(&self.sessions);
+        let timeout = self.config.session_timeout();
+        let interval = SWEEP_INTERVAL.min(timeout);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let now = Instant::now();
+            let mut sessions = lock_or_recover(&sessions);
+            let before = sessions.len();
+            sessions.retain(|_, state| now.duration_since(state.last_activity) < timeout);
+            if sessions.len() < before {
+                println!("🧹 清理了 {} 个超时的猜数字会话", before - sessions.len());
+            }
+        })
     }
 }
 
@@ -433,290 +1353,507 @@ impl GuessGameState {
 //     None
 // }
 fn main() -> std::io::Result<()> {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(2);
+        }
+    };
+
     println!("🎮 小游戏服务器启动中...");
-    println!("🌐 访问 http://localhost:8082 开始游戏");
-    println!("🏎️ 赛车游戏API已就绪");
+    println!(
+        "⚙️ 猜数字普通难度范围 {}-{}，最多 {} 次；赛车每局最多 {} 名玩家，抢跑阈值 {}ms，空闲超时 {} 秒，最多 {} 局同时进行",
+        config.guess.min,
+        config.guess.max,
+        config.guess.max_attempts,
+        config.racing.max_players,
+        config.racing.false_start_threshold_ms,
+        config.racing.game_timeout_secs,
+        config.racing.max_games,
+    );
 
     // 初始化数据库
-    let db_manager = Arc::new(SyncDatabaseManager::new());
-
-    let server = Server::http("0.0.0.0:8082").unwrap();
-    let game_state = Arc::new(Mutex::new(GuessGameState::new()));
-    let racing_storage = Arc::new(Mutex::new(GameStateStorage::new()));
-
-    for mut request in server.incoming_requests() {
-        let game_state = game_state.clone();
-        let racing_storage = racing_storage.clone();
-        let db_manager = db_manager.clone();
-
-        let response = match (request.method(), request.url()) {
-            (Method::Get, "/api/info") => {
-                let state = game_state.lock().unwrap();
-                let info = GameInfo {
-                    range: (state.min_number, state.max_number),
-                    max_attempts: state.max_attempts,
-                };
-                Response::from_string(serde_json::to_string(&info).unwrap())
-                    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-            }
-            (Method::Get, url) if url.starts_with("/api/guess/") => {
-                let number_str = url.trim_start_matches("/api/guess/");
-                if let Ok(number) = number_str.parse::<u32>() {
-                    let mut state = game_state.lock().unwrap();
-                    let response = state.guess(number);
-                    Response::from_string(serde_json::to_string(&response).unwrap())
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                } else {
-                    Response::from_string("{\"error\": \"无效的数字\"}")
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                        .with_status_code(StatusCode::from(400))
-                }
-            }
-            (Method::Post, "/api/reset") => {
-                let mut state = game_state.lock().unwrap();
-                state.reset();
-                let info = GameInfo {
-                    range: (state.min_number, state.max_number),
-                    max_attempts: state.max_attempts,
-                };
-                Response::from_string(serde_json::to_string(&info).unwrap())
-                    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-            }
-            (Method::Get, "/api/games") => {
-                let games = vec!["猜数字游戏".to_string(), "赛车起跑反应".to_string()];
-                let response = GameListResponse { games };
-                Response::from_string(serde_json::to_string(&response).unwrap())
-                    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-            }
-            (Method::Get, "/api/leaderboard") => {
-                let limit = request
-                    .url()
-                    .split('?')
-                    .nth(1)
-                    .and_then(|query| {
-                        query
-                            .split('&')
-                            .find(|param| param.starts_with("limit="))
-                            .and_then(|param| param[6..].parse::<i64>().ok())
-                    })
-                    .unwrap_or(10);
-                
-                let db = db_manager.clone();
-                        match db.get_leaderboard(limit) {
-                    Ok(leaderboard) => {
-                        Response::from_string(serde_json::to_string(&leaderboard).unwrap())
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                    }
-                    Err(e) => {
-                        Response::from_string(&format!("{{\"error\": \"获取排行榜失败: {}\"}}", e))
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                            .with_status_code(StatusCode::from(500))
-                    }
-                }
-            }
-            (Method::Post, "/api/racing/create") => {
-                let mut content = String::new();
-                request.as_reader().read_to_string(&mut content).unwrap();
-                let req: CreateGameRequest = serde_json::from_str(&content).unwrap_or_else(|_| {
-                    CreateGameRequest {
-                        player_count: 2,
-                        round_count: 3,
-                        player_names: vec!["玩家1".to_string(), "玩家2".to_string()],
-                    }
-                });
-                let storage = racing_storage.lock().unwrap();
-                let response = storage.create_game(req);
-                Response::from_string(serde_json::to_string(&response).unwrap())
-                    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-            }
-            (Method::Post, url) if url.starts_with("/api/racing/start/") => {
-                let game_id = url.trim_start_matches("/api/racing/start/");
-                let storage = racing_storage.lock().unwrap();
-                if let Some(response) = storage.start_game(game_id) {
-                    Response::from_string(serde_json::to_string(&response).unwrap())
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                } else {
-                    Response::from_string("{\"error\": \"游戏未找到\"}")
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                        .with_status_code(StatusCode::from(404))
-                }
-            }
-            (Method::Post, url) if url.starts_with("/api/racing/trigger/") => {
-                let game_id = url.trim_start_matches("/api/racing/trigger/");
-                let storage = racing_storage.lock().unwrap();
-                if let Some(response) = storage.trigger_green_light(game_id) {
-                    Response::from_string(serde_json::to_string(&response).unwrap())
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                } else {
-                    Response::from_string("{\"error\": \"游戏未找到\"}")
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                        .with_status_code(StatusCode::from(404))
-                }
-            }
-            (Method::Post, "/api/racing/react") => {
-                let mut content = String::new();
-                request.as_reader().read_to_string(&mut content).unwrap();
-                println!("收到反应记录请求: {}", content);
-                
-                // 使用serde_json正确解析JSON
-                match serde_json::from_str::<ReactionRequest>(&content) {
-                    Ok(req) => {
-                        println!("解析成功: game_id={}, player_id={}, reaction_time={}", req.game_id, req.player_id, req.reaction_time);
-                        let storage = racing_storage.lock().unwrap();
-                        match storage.record_reaction(&req.game_id, req.player_id, req.reaction_time) {
-                            Ok(result) => {
-                                Response::from_string(serde_json::to_string(&result).unwrap())
-                                    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                            }
-                            Err(error) => {
-                                Response::from_string(&format!("{{\"error\": \"{}\"}}", error))
-                                    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                                    .with_status_code(StatusCode::from(400))
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!("JSON解析错误: {}", e);
-                        Response::from_string(&format!("{{\"error\": \"请求格式错误: {}\"}}", e))
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                            .with_status_code(StatusCode::from(400))
-                    }
-                }
-            }
-            (Method::Post, url) if url.starts_with("/api/racing/finish/") => {
-                let game_id = url.trim_start_matches("/api/racing/finish/");
-                let storage = racing_storage.lock().unwrap();
-                if let Some(result) = storage.finish_round(game_id) {
-                    Response::from_string(serde_json::to_string(&result).unwrap())
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                } else {
-                    Response::from_string("{\"error\": \"游戏未找到\"}")
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                        .with_status_code(StatusCode::from(404))
-                }
-            }
-            (Method::Get, url) if url.starts_with("/api/racing/status/") => {
-                let game_id = url.trim_start_matches("/api/racing/status/");
-                let storage = racing_storage.lock().unwrap();
-                if let Some(response) = storage.get_game(game_id) {
-                    Response::from_string(serde_json::to_string(&response).unwrap())
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                } else {
-                    Response::from_string("{\"error\": \"游戏未找到\"}")
-                        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                        .with_status_code(StatusCode::from(404))
-                }
-            }
-            (Method::Get, "/") | (Method::Get, "/index.html") => {
-                let file = std::fs::read("./static/index.html").unwrap();
-                Response::from_data(file).with_header(Header::from_bytes("Content-Type", "text/html").unwrap())
-            }
-            (Method::Post, "/api/database/save") => {
-                let mut content = String::new();
-                request.as_reader().read_to_string(&mut content).unwrap();
-                
-                #[derive(Deserialize)]
-                struct SaveRequest {
-                    game_id: String,
-                    player_name: String,
-                    score: i32,
-                    reaction_time: Option<f64>,
-                }
-                
-                match serde_json::from_str::<SaveRequest>(&content) {
-                    Ok(req) => {
-                        let game_id = req.game_id.clone();
-            let player_name = req.player_name.clone();
-            let score = req.score;
-            let reaction_time = req.reaction_time;
-            let db = db_manager.clone();
-            let _ = db.save_game_record(&game_id, &player_name, score, reaction_time);
-            
-            Response::from_string("游戏记录已保存")
-                .with_header(Header::from_str("Content-Type: text/plain; charset=utf-8").unwrap())
-                    }
-                    Err(e) => {
-                        Response::from_string(&format!("{{\"error\": \"请求格式错误: {}\"}}", e))
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                            .with_status_code(StatusCode::from(400))
-                    }
-                }
-            }
-            (Method::Get, url) if url.starts_with("/api/database/player/") => {
-                let player_name = url.trim_start_matches("/api/database/player/");
-                let limit = request
-                    .url()
-                    .split('?')
-                    .nth(1)
-                    .and_then(|query| {
-                        query
-                            .split('&')
-                            .find(|param| param.starts_with("limit="))
-                            .and_then(|param| param[6..].parse::<i64>().ok())
-                    })
-                    .unwrap_or(10);
-                
-                let db = db_manager.clone();
-                        match db.get_player_history(player_name, limit) {
-                    Ok(records) => {
-                        Response::from_string(serde_json::to_string(&records).unwrap())
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                    }
-                    Err(e) => {
-                        Response::from_string(&format!("{{\"error\": \"获取玩家历史记录失败: {}\"}}", e))
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                            .with_status_code(StatusCode::from(500))
-                    }
-                }
-            }
-            (Method::Get, "/api/database/stats") => {
-                let db = db_manager.clone();
-                match db.get_stats() {
-                    Ok((total_records, total_players)) => {
-                        let stats = serde_json::json!({
-                            "total_records": total_records,
-                            "total_players": total_players,
-                            "status": "connected"
-                        });
-                        Response::from_string(serde_json::to_string(&stats).unwrap())
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                    }
-                    Err(e) => {
-                        Response::from_string(&format!("{{\"error\": \"获取数据库统计失败: {}\"}}", e))
-                            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
-                            .with_status_code(StatusCode::from(500))
-                    }
+    let backend = DatabaseBackend::parse(&config.server.database);
+    let db_manager = match SyncDatabaseManager::open(&backend) {
+        Ok(manager) => {
+            println!("🗄️ 数据库已就绪: {:?}", backend);
+            manager
+        }
+        Err(e) => {
+            println!("⚠️ 打开数据库失败({:?}): {}，改用内存数据库", backend, e);
+            SyncDatabaseManager::new()
+        }
+    };
+    let db_manager = Arc::new(db_manager);
+
+    let server = Server::http(config.server.bind.as_str()).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let server = Arc::new(server);
+    if let Some(addr) = server.server_addr().to_ip() {
+        println!("🌐 监听 {}，访问 http://localhost:{} 开始游戏", addr, addr.port());
+    }
+    let state = Arc::new(AppState {
+        guess_sessions: Arc::new(GuessSessionStorage::new(db_manager.clone(), config.guess.clone())),
+        racing: Arc::new(GameStateStorage::with_database(db_manager.clone(), config.racing.clone())),
+        database: db_manager,
+        static_files: StaticFiles::new(config.server.static_dir.as_deref()),
+    });
+    println!("📁 静态资源: {}", state.static_files.describe());
+    let mut snapshot_path = config.racing.snapshot_path();
+    if let Some(path) = snapshot_path.clone() {
+        match state.racing.restore_snapshot(&path) {
+            Ok(0) => {}
+            Ok(count) => println!("💾 从 {} 恢复了 {} 局游戏", path.display(), count),
+            Err(e) => match snapshot::set_aside(&path) {
+                Ok(kept) => println!(
+                    "⚠️ 读取游戏快照失败 {}: {}，原文件已改名为 {}，从空白状态启动",
+                    path.display(),
+                    e,
+                    kept.display()
+                ),
+                // 无法保留原文件时不再写快照，以免覆盖它
+                Err(rename_error) => {
+                    println!(
+                        "⚠️ 读取游戏快照失败 {}: {}，且无法改名保留（{}），本次运行不保存快照",
+                        path.display(),
+                        e,
+                        rename_error
+                    );
+                    snapshot_path = None;
                 }
+            },
+        }
+    }
+    if let Some(path) = &snapshot_path {
+        state.racing.start_snapshotter(path.clone(), config.racing.snapshot_interval());
+    }
+    state.racing.start_light_scheduler();
+    state.racing.start_sweeper();
+    state.guess_sessions.start_sweeper();
+    let router = Arc::new(handlers::routes());
+
+    // 多个工作线程共同从服务器取请求，慢请求不会阻塞其他玩家的反应
+    let shutdown_at: Arc<OnceLock<Instant>> = Arc::new(OnceLock::new());
+    let workers: Vec<_> = (0..config.server.worker_threads)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let router = Arc::clone(&router);
+            let state = Arc::clone(&state);
+            let shutdown_at = Arc::clone(&shutdown_at);
+            thread::spawn(move || serve_requests(&server, &router, &state, &shutdown_at))
+        })
+        .collect();
+    println!("🏎️ 赛车游戏API已就绪");
+    println!("🧵 {} 个工作线程处理请求", config.server.worker_threads);
+
+    // 收到 SIGINT/SIGTERM 后工作线程只处理已排队的请求，队列清空或超过时限即退出；再次收到信号立即退出
+    let signal_time = Arc::clone(&shutdown_at);
+    ctrlc::set_handler(move || {
+        if signal_time.set(Instant::now()).is_err() {
+            println!("⚠️ 再次收到退出信号，立即退出");
+            std::process::exit(130);
+        }
+        println!("🛑 收到退出信号，等待处理中的请求完成...");
+    })
+    .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    // 释放最后一个引用即关闭监听端口，之后的连接直接被拒绝
+    drop(server);
+    println!("✅ 所有请求已处理完毕，已停止监听");
+
+    // 退出前再保存一次，保留最后一次定期快照之后的变化
+    if let Some(path) = &snapshot_path {
+        match state.racing.save_snapshot(path) {
+            Ok(count) => println!("💾 已保存 {} 局游戏到 {}", count, path.display()),
+            Err(e) => println!("⚠️ 保存游戏快照失败 {}: {}", path.display(), e),
+        }
+    }
+    match state.database.flush() {
+        Ok(()) => println!("🗄️ 数据库已写入磁盘"),
+        Err(e) => println!("⚠️ 写入数据库失败: {}", e),
+    }
+    if matches!(backend, DatabaseBackend::Memory) {
+        let (records, _) = state.database.get_stats().unwrap_or_default();
+        println!("⚠️ 使用的是内存数据库，{} 条游戏记录不会保留", records);
+    }
+
+    println!("👋 服务器已安全关闭");
+    Ok(())
+}
+
+fn serve_requests(server: &Server, router: &Router<AppState>, state: &AppState, shutdown_at: &OnceLock<Instant>) {
+    loop {
+        let next = match shutdown_at.get() {
+            // 退出阶段不再等待新请求，只取队列中现有的，超过时限后剩余请求直接放弃
+            Some(at) if at.elapsed() >= SHUTDOWN_DRAIN_LIMIT => break,
+            Some(_) => match server.try_recv() {
+                Ok(None) => break,
+                other => other,
+            },
+            None => server.recv_timeout(SHUTDOWN_POLL),
+        };
+        let mut request = match next {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("接收请求失败: {}", e);
+                continue;
             }
-            (Method::Get, url) => {
-                let current_dir = std::env::current_dir().unwrap();
-                let file_path = current_dir.join("static").join(url.trim_start_matches('/'));
-                println!("尝试访问文件: {}", file_path.display());
-                match std::fs::read(&file_path) {
-                    Ok(file) => {
-                        let content_type = match url.split('.').last() {
-                            Some("html") => "text/html",
-                            Some("css") => "text/css",
-                            Some("js") => "application/javascript",
-                            Some("json") => "application/json",
-                            _ => "text/plain",
-                        };
-                        Response::from_data(file).with_header(Header::from_bytes("Content-Type", content_type).unwrap())
-                    },
-                    Err(e) => {
-                        println!("文件访问错误 {}: {}", file_path.display(), e);
-                        Response::from_string("404 Not Found")
-                            .with_status_code(StatusCode::from(404))
-                    }
-                }
+        };
+
+        // 尽早记录收到请求的时刻，作为服务器计时依据
+        let received_at = Instant::now();
+
+        // 赛车游戏实时事件：ws://host/racing/{game_id}
+        if request.method() == &Method::Get
+            && request.url().starts_with("/racing/")
+            && websocket::is_upgrade_request(&request)
+        {
+            let path = request.url().split('?').next().unwrap_or_default();
+            let game_id = router::percent_decode(path.trim_start_matches("/racing/"), false);
+            let upgrade = panic::catch_unwind(AssertUnwindSafe(|| {
+                websocket::handle_racing_socket(request, &game_id, &state.racing)
+            }));
+            if upgrade.is_err() {
+                println!("⚠️ WebSocket握手出错: {}", game_id);
             }
-            _ => {
-                Response::from_string("404 Not Found")
-                    .with_status_code(StatusCode::from(404))
+            continue;
+        }
+
+        // 单个请求出错只返回500，不影响工作线程继续服务
+        let url = request.url().to_string();
+        let response = panic::catch_unwind(AssertUnwindSafe(|| router.dispatch(&mut request, state, received_at)))
+            .unwrap_or_else(|_| {
+                println!("⚠️ 处理请求时出错: {} {}", request.method(), url);
+                AppError::Internal("处理请求时出错".to_string()).to_response()
+            });
+        if let Err(e) = request.respond(response) {
+            println!("发送响应失败 {}: {}", url, e);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_STATES: [GameState; 6] = [
+        GameState::Waiting,
+        GameState::Countdown,
+        GameState::Ready,
+        GameState::Racing,
+        GameState::Finished,
+        GameState::GameOver,
+    ];
+
+    fn racing_game(round_count: u8) -> RacingGame {
+        let names = vec!["甲".to_string(), "乙".to_string()];
+        RacingGame::new(2, round_count, names, ScoringRules::default(), false, RaceTiming::default())
+    }
+
+    fn ready_all(game: &mut RacingGame) {
+        for id in 1..=2 {
+            game.set_ready(id, true).unwrap();
+        }
+    }
+
+    #[test]
+    fn only_listed_transitions_are_allowed() {
+        use GameState::*;
+        let allowed = [
+            (Waiting, Countdown),
+            (Countdown, Ready),
+            (Ready, Racing),
+            (Racing, Finished),
+            (Finished, Waiting),
+            (Finished, GameOver),
+        ];
+
+        for from in &ALL_STATES {
+            for to in &ALL_STATES {
+                let expected = allowed.iter().any(|(a, b)| a == from && b == to);
+                assert_eq!(from.can_transition_to(to), expected, "{:?} -> {:?}", from, to);
             }
-        };
+        }
+    }
 
-        request.respond(response).unwrap();
+    #[test]
+    fn a_full_round_walks_the_state_machine() {
+        let mut game = racing_game(1);
+        ready_all(&mut game);
+        game.start_round().unwrap();
+        assert_eq!(game.game_state, GameState::Countdown);
+
+        // 红灯全部亮起加上随机等待不会超过 60 秒，推进后应已亮绿灯
+        let later = Instant::now() + Duration::from_secs(60);
+        game.advance_lights(later);
+        assert_eq!(game.game_state, GameState::Racing);
+
+        game.record_reaction(1, None, later).unwrap();
+        game.finish_round().unwrap();
+        assert_eq!(game.game_state, GameState::GameOver);
+        assert_eq!(game.current_round, 1);
+
+        let err = game.start_round().unwrap_err();
+        assert_eq!(err, AppError::State(StateError::RoundsExhausted { max_rounds: 1 }));
+        assert_eq!((err.status(), err.code()), (409, "rounds_exhausted"));
     }
-    Ok(())
-}
\ No newline at end of file
+
+    #[test]
+    fn unfinished_rounds_return_to_waiting() {
+        let mut game = racing_game(3);
+        ready_all(&mut game);
+        game.start_round().unwrap();
+        game.advance_lights(Instant::now() + Duration::from_secs(60));
+        game.finish_round().unwrap();
+
+        assert_eq!(game.game_state, GameState::Waiting);
+        assert!(game.players.iter().all(|p| !p.is_ready));
+    }
+
+    #[test]
+    fn illegal_transitions_map_to_409_and_leave_the_game_untouched() {
+        let mut game = racing_game(3);
+
+        let err = game.finish_round().unwrap_err();
+        assert_eq!(
+            err,
+            AppError::State(StateError::IllegalTransition { from: GameState::Waiting, to: GameState::Finished })
+        );
+        assert_eq!((err.status(), err.code()), (409, "illegal_transition"));
+        assert_eq!(game.game_state, GameState::Waiting);
+        assert_eq!(game.current_round, 0);
+        assert!(game.round_results.is_empty());
+
+        let err = game.trigger_green_light(Instant::now()).unwrap_err();
+        assert_eq!(err.code(), "illegal_transition");
+        assert!(game.green_light_time.is_none());
+    }
+
+    #[test]
+    fn actions_in_the_wrong_state_map_to_409() {
+        let mut game = racing_game(3);
+
+        let err = game.record_reaction(1, None, Instant::now()).unwrap_err();
+        assert_eq!(
+            err,
+            AppError::State(StateError::WrongState { expected: GameState::Racing, actual: GameState::Waiting })
+        );
+        assert_eq!((err.status(), err.code()), (409, "wrong_state"));
+
+        ready_all(&mut game);
+        game.start_round().unwrap();
+        let err = game.set_ready(1, true).unwrap_err();
+        assert_eq!(err.code(), "wrong_state");
+        assert_eq!(err.status(), 409);
+    }
+
+    #[test]
+    fn rounds_start_only_when_everyone_is_ready() {
+        let mut game = racing_game(3);
+
+        let err = game.start_round().unwrap_err();
+        assert_eq!(err, AppError::State(StateError::PlayersNotReady { players: vec![1, 2] }));
+        assert_eq!((err.status(), err.code()), (409, "players_not_ready"));
+
+        game.set_ready(1, true).unwrap();
+        let err = game.start_round().unwrap_err();
+        assert_eq!(err, AppError::State(StateError::PlayersNotReady { players: vec![2] }));
+        assert_eq!(game.game_state, GameState::Waiting);
+
+        game.set_ready(2, true).unwrap();
+        game.start_round().unwrap();
+        assert_eq!(game.game_state, GameState::Countdown);
+    }
+
+    #[test]
+    fn press_times_are_bounded_by_the_measured_latency() {
+        let mut game = racing_game(3);
+        // 客户端时钟比服务器慢 5000ms，对时往返 40ms，单程按 20ms 计
+        let synced_at = Instant::now();
+        let server_time = server_clock_ms(synced_at);
+        let sample = ClockSample { client_send: server_time - 5020.0, server_time, client_receive: server_time - 4980.0 };
+        for id in 1..=2 {
+            assert_eq!(game.sync_clock(id, Some(&sample), synced_at).unwrap(), Some(5000.0));
+        }
+
+        ready_all(&mut game);
+        game.start_round().unwrap();
+        game.advance_lights(Instant::now() + Duration::from_secs(60));
+        let green_ms = server_clock_ms(game.green_light_time.unwrap());
+        let received_at = game.green_light_time.unwrap() + Duration::from_millis(500);
+        let received_ms = server_clock_ms(received_at);
+        let max_transit = 20.0 + game.timing.clock_tolerance_ms;
+
+        // 声称比允许的窗口更早按下，拒绝且不记录
+        let too_early = received_ms - max_transit - 5.0 - 5000.0;
+        let err = game.record_reaction(1, Some(too_early), received_at).unwrap_err();
+        assert_eq!((err.status(), err.code()), (400, "rejected"));
+        assert!(game.reacted_players.is_empty());
+
+        // 窗口内的时间戳按校正后的按键时刻计算
+        let in_window = received_ms - max_transit + 1.0 - 5000.0;
+        let result = game.record_reaction(1, Some(in_window), received_at).unwrap();
+        let expected = received_ms - max_transit + 1.0 - green_ms;
+        assert!((result.reaction_time.unwrap() - expected).abs() < 1e-6);
+
+        // 晚于收到请求的时间戳按收到请求的时刻计算
+        let after_arrival = received_ms + 300.0 - 5000.0;
+        let result = game.record_reaction(2, Some(after_arrival), received_at).unwrap();
+        assert!((result.reaction_time.unwrap() - (received_ms - green_ms)).abs() < 1e-6);
+    }
+
+    fn create_request(player_count: i64, round_count: i64, names: &[&str]) -> CreateGameRequest {
+        CreateGameRequest {
+            player_count,
+            round_count,
+            player_names: names.iter().map(|name| name.to_string()).collect(),
+            ..CreateGameRequest::default()
+        }
+    }
+
+    fn invalid_fields(request: &CreateGameRequest, limits: &RacingConfig) -> Vec<String> {
+        match request.validate(limits) {
+            Ok(()) => Vec::new(),
+            Err(AppError::Validation(errors)) => errors.into_iter().map(|e| e.field).collect(),
+            Err(other) => panic!("应返回字段校验错误，实际为 {:?}", other),
+        }
+    }
+
+    #[test]
+    fn valid_requests_pass_and_missing_names_use_defaults() {
+        let limits = RacingConfig::default();
+        assert!(CreateGameRequest::default().validate(&limits).is_ok());
+
+        let request = create_request(3, 5, &[" 小明 "]);
+        assert!(request.validate(&limits).is_ok());
+        assert_eq!(request.resolved_names(), vec!["小明", "玩家2", "玩家3"]);
+    }
+
+    #[test]
+    fn counts_outside_the_configured_limits_are_rejected() {
+        let limits = RacingConfig::default();
+        assert_eq!(invalid_fields(&create_request(0, 3, &[]), &limits), vec!["player_count"]);
+        assert_eq!(invalid_fields(&create_request(i64::from(limits.max_players) + 1, 3, &[]), &limits), vec!["player_count"]);
+        // 超出 u8 范围的值同样得到字段错误，名称检查不会按这个人数生成默认名称
+        assert_eq!(invalid_fields(&create_request(300, 3, &["甲"]), &limits), vec!["player_count"]);
+        assert_eq!(invalid_fields(&create_request(i64::MAX, 3, &[]), &limits), vec!["player_count"]);
+        assert_eq!(invalid_fields(&create_request(-1, -1, &["甲"]), &limits), vec!["player_count", "round_count", "player_names"]);
+        assert_eq!(invalid_fields(&create_request(2, 256, &[]), &limits), vec!["round_count"]);
+        assert_eq!(invalid_fields(&create_request(2, 2, &[]), &limits), vec!["round_count"]);
+        assert_eq!(invalid_fields(&create_request(2, 11, &[]), &limits), vec!["round_count"]);
+
+        let narrow = RacingConfig { min_players: 2, max_players: 2, min_rounds: 1, max_rounds: 1, ..RacingConfig::default() };
+        assert_eq!(invalid_fields(&create_request(1, 1, &[]), &narrow), vec!["player_count"]);
+        assert!(create_request(2, 1, &[]).validate(&narrow).is_ok());
+    }
+
+    #[test]
+    fn bad_names_are_reported_per_field() {
+        let limits = RacingConfig::default();
+        let long_name = "长".repeat(limits.max_name_length + 1);
+        let request = create_request(3, 3, &["  ", &long_name, "小红"]);
+        assert_eq!(invalid_fields(&request, &limits), vec!["player_names[0]", "player_names[1]"]);
+
+        let exact = "长".repeat(limits.max_name_length);
+        assert!(create_request(2, 3, &[&exact]).validate(&limits).is_ok());
+
+        assert_eq!(invalid_fields(&create_request(1, 3, &["甲", "乙"]), &limits), vec!["player_names"]);
+    }
+
+    #[test]
+    fn duplicate_names_including_defaults_are_rejected() {
+        let limits = RacingConfig::default();
+        assert_eq!(invalid_fields(&create_request(2, 3, &["甲", " 甲"]), &limits), vec!["player_names[0]"]);
+        // 第一个玩家取名“玩家2”，与第二个玩家的默认名称冲突
+        assert_eq!(invalid_fields(&create_request(2, 3, &["玩家2"]), &limits), vec!["player_names[0]"]);
+
+        // 多个字段出错时一次全部返回
+        let request = create_request(2, 20, &["甲", "甲"]);
+        assert_eq!(invalid_fields(&request, &limits), vec!["round_count", "player_names[0]"]);
+    }
+
+    // 答案固定的猜数字游戏，便于断言每一步的结果
+    fn guess_game(target: u32, max_attempts: u32) -> GuessGameState {
+        let settings = GuessSettings { min: 1, max: 100, max_attempts };
+        let mut state = GuessGameState::new("小明".to_string(), Difficulty::Custom, settings);
+        state.target_number = target;
+        state
+    }
+
+    #[test]
+    fn wrong_guesses_narrow_the_range_without_revealing_the_answer() {
+        let mut state = guess_game(42, 5);
+
+        let (response, completed) = state.guess(50).unwrap();
+        assert_eq!((response.range, response.remaining_attempts), ((1, 49), 4));
+        assert!(!response.correct);
+        assert!(completed.is_none());
+
+        let (response, _) = state.guess(10).unwrap();
+        assert_eq!((response.range, response.attempts), ((11, 49), 2));
+        assert_eq!(response.status, GuessStatus::Playing);
+        assert_eq!(response.answer, None);
+
+        // 超出范围的猜测被拒绝且不计入次数
+        let err = state.guess(101).unwrap_err();
+        assert_eq!((err.status(), err.code()), (400, "invalid_parameter"));
+        assert_eq!(state.attempts, 2);
+    }
+
+    #[test]
+    fn a_correct_guess_wins_and_blocks_further_guesses() {
+        let mut state = guess_game(42, 5);
+        state.guess(30).unwrap();
+
+        let (response, completed) = state.guess(42).unwrap();
+        assert!(response.correct);
+        assert_eq!(response.status, GuessStatus::Won);
+        assert_eq!(response.answer, Some(42));
+        let completed = completed.expect("猜中后应记录成绩");
+        assert_eq!((completed.attempts, completed.score()), (2, 40));
+
+        let err = state.guess(42).unwrap_err();
+        assert_eq!(err, AppError::State(StateError::GuessFinished { status: GuessStatus::Won }));
+        assert_eq!((err.status(), err.code()), (409, "guess_finished"));
+        assert_eq!(state.attempts, 2);
+    }
+
+    #[test]
+    fn running_out_of_attempts_loses_and_reveals_the_answer() {
+        let mut state = guess_game(42, 2);
+        state.guess(1).unwrap();
+
+        let (response, completed) = state.guess(100).unwrap();
+        assert_eq!(response.status, GuessStatus::Lost);
+        assert_eq!((response.remaining_attempts, response.answer), (0, Some(42)));
+        assert!(completed.is_none());
+        assert_eq!(state.info().answer, Some(42));
+
+        let err = state.guess(42).unwrap_err();
+        assert_eq!(err, AppError::State(StateError::GuessFinished { status: GuessStatus::Lost }));
+    }
+
+    #[test]
+    fn reset_starts_a_fresh_round_with_the_same_settings() {
+        for finish in [42, 1] {
+            let mut state = guess_game(42, 1);
+            state.guess(finish).unwrap();
+            assert_ne!(state.status, GuessStatus::Playing);
+
+            state.reset();
+            let info = state.info();
+            assert_eq!(info.status, GuessStatus::Playing);
+            assert_eq!((info.range, info.attempts, info.max_attempts), ((1, 100), 0, 1));
+            assert_eq!(info.answer, None);
+            assert_eq!(info.difficulty, Difficulty::Custom);
+            assert_eq!(state.player_name, "小明");
+            assert!((1..=100).contains(&state.target_number));
+
+            let target = state.target_number;
+            assert!(state.guess(target).unwrap().0.correct);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::error::AppError;
+
+pub type HttpResponse = Response<Cursor<Vec<u8>>>;
+pub type HandlerResult = Result<HttpResponse, AppError>;
+pub type Handler<S> = fn(&mut RouteContext, &S) -> HandlerResult;
+
+// 路由模板中的一段：固定文字、{name} 参数，或匹配剩余路径的 {*name}
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+struct Route<S> {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler<S>,
+}
+
+impl<S> Route<S> {
+    fn has_rest(&self) -> bool {
+        matches!(self.segments.last(), Some(Segment::Rest(_)))
+    }
+}
+
+// 处理函数可用的请求信息，路径参数和查询参数均已解码
+pub struct RouteContext<'a> {
+    pub request: &'a mut Request,
+    pub received_at: Instant,
+    params: HashMap<String, String>,
+    query: HashMap<String, String>,
+}
+
+impl RouteContext<'_> {
+    pub fn param(&self, name: &str) -> &str {
+        self.params.get(name).map(String::as_str).unwrap_or_default()
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+
+    // 解析查询参数，参数缺失时返回 None，格式错误时返回 invalid_parameter
+    pub fn query_parsed<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, AppError> {
+        self.query(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| AppError::invalid_parameter(name, format!("无法解析: {}", value)))
+            })
+            .transpose()
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.request
+            .headers()
+            .iter()
+            .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str().to_string())
+    }
+
+    pub fn body(&mut self) -> Result<String, AppError> {
+        let mut content = String::new();
+        self.request
+            .as_reader()
+            .read_to_string(&mut content)
+            .map_err(|e| AppError::MalformedBody(e.to_string()))?;
+        Ok(content)
+    }
+
+    pub fn json<T: DeserializeOwned>(&mut self) -> Result<T, AppError> {
+        let content = self.body()?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+// 添加响应头；名称或值不合法时跳过该响应头而不是让请求失败
+pub fn with_header<R: Read>(response: Response<R>, name: &str, value: &str) -> Response<R> {
+    match Header::from_bytes(name, value) {
+        Ok(header) => response.with_header(header),
+        Err(_) => {
+            println!("无效的响应头: {}: {}", name, value);
+            response
+        }
+    }
+}
+
+pub fn json_response<T: Serialize>(value: &T) -> HandlerResult {
+    let body = serde_json::to_string(value).map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(with_header(Response::from_string(body), "Content-Type", "application/json"))
+}
+
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Router<S> {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    // 按注册顺序匹配，先注册的路由优先
+    pub fn route(mut self, method: Method, pattern: &str, handler: Handler<S>) -> Self {
+        let segments = split_path(pattern)
+            .map(|segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => match name.strip_prefix('*') {
+                    Some(rest) => Segment::Rest(rest.to_string()),
+                    None => Segment::Param(name.to_string()),
+                },
+                None => Segment::Literal(segment.to_string()),
+            })
+            .collect();
+        self.routes.push(Route { method, segments, handler });
+        self
+    }
+
+    pub fn dispatch(&self, request: &mut Request, state: &S, received_at: Instant) -> HttpResponse {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+        let segments: Vec<String> = split_path(path).map(|s| percent_decode(s, false)).collect();
+        // HEAD 按 GET 路由处理，tiny_http 发送响应时会省略响应体
+        let method = match request.method() {
+            Method::Head => Method::Get,
+            other => other.clone(),
+        };
+
+        let mut path_matched = false;
+        for route in &self.routes {
+            let Some(params) = match_segments(&route.segments, &segments) else {
+                continue;
+            };
+            if route.method != method {
+                // {*name} 路由能匹配任意剩余路径，不能说明该路径存在
+                path_matched |= !route.has_rest();
+                continue;
+            }
+            // 该路径已有其他方法的具体路由时不交给 {*name} 兜底，返回405
+            if path_matched && route.has_rest() {
+                continue;
+            }
+
+            let mut context = RouteContext {
+                request,
+                received_at,
+                params,
+                query: parse_query(query),
+            };
+            return (route.handler)(&mut context, state).unwrap_or_else(|error| error.to_response());
+        }
+
+        let error = if path_matched {
+            AppError::MethodNotAllowed(request.method().to_string())
+        } else {
+            AppError::NotFound(path.to_string())
+        };
+        error.to_response()
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn match_segments(pattern: &[Segment], segments: &[String]) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    for (index, segment) in pattern.iter().enumerate() {
+        match segment {
+            Segment::Rest(name) => {
+                params.insert(name.clone(), segments.get(index..)?.join("/"));
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if segments.get(index)? != literal {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), segments.get(index)?.clone());
+            }
+        }
+    }
+    (pattern.len() == segments.len()).then_some(params)
+}
+
+// 解析 a=1&b=%E5%BC%A0 形式的查询字符串，同名参数以第一个为准
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params
+            .entry(percent_decode(key, true))
+            .or_insert_with(|| percent_decode(value, true));
+    }
+    params
+}
+
+// URL 百分号解码，非法的转义原样保留；查询字符串中的 + 表示空格
+pub fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => match bytes.get(index + 1..index + 3) {
+                Some(&[high, low]) if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                    decoded.push(hex_value(high) << 4 | hex_value(low));
+                    index += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unused(_ctx: &mut RouteContext, _state: &()) -> HandlerResult {
+        unreachable!()
+    }
+
+    // 按 dispatch 的方式拆分并解码路径，再与路由模板匹配
+    fn matches(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let route = Router::<()>::new().route(Method::Get, pattern, unused).routes.remove(0);
+        let segments: Vec<String> = split_path(path).map(|s| percent_decode(s, false)).collect();
+        let mut params: Vec<_> = match_segments(&route.segments, &segments)?.into_iter().collect();
+        params.sort();
+        Some(params)
+    }
+
+    fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn percent_decode_handles_utf8_and_plus() {
+        assert_eq!(percent_decode("%E5%BC%A0%E4%B8%89", false), "张三");
+        assert_eq!(percent_decode("a%2Fb%2fc", false), "a/b/c");
+        assert_eq!(percent_decode("a+b", true), "a b");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("%2B", true), "+");
+    }
+
+    #[test]
+    fn percent_decode_keeps_invalid_escapes() {
+        assert_eq!(percent_decode("100%", false), "100%");
+        assert_eq!(percent_decode("%g1%1", false), "%g1%1");
+        assert_eq!(percent_decode("%%41", false), "%A");
+        // 解码结果不是合法 UTF-8 时用替换字符代替，不会出错
+        assert_eq!(percent_decode("%ff%fe%", false), "\u{FFFD}\u{FFFD}%");
+    }
+
+    #[test]
+    fn parse_query_decodes_pairs_and_keeps_the_first_duplicate() {
+        let query = parse_query("game=guess_number&name=%E5%BC%A0+%E4%B8%89&limit=5&limit=50&flag&&=x");
+        assert_eq!(query.get("game").map(String::as_str), Some("guess_number"));
+        assert_eq!(query.get("name").map(String::as_str), Some("张 三"));
+        assert_eq!(query.get("limit").map(String::as_str), Some("5"));
+        assert_eq!(query.get("flag").map(String::as_str), Some(""));
+        assert_eq!(query.get("").map(String::as_str), Some("x"));
+        assert_eq!(query.len(), 5);
+        assert!(parse_query("").is_empty());
+    }
+
+    #[test]
+    fn match_segments_binds_literals_and_params() {
+        assert_eq!(matches("/api/game/{game_id}", "/api/game/game_42"), Some(pairs(&[("game_id", "game_42")])));
+        assert_eq!(matches("/api/game/{game_id}", "/api/game/game_42/"), Some(pairs(&[("game_id", "game_42")])));
+        assert_eq!(matches("/api/game/{game_id}", "/api/game/a%2Fb"), Some(pairs(&[("game_id", "a/b")])));
+        assert_eq!(matches("/api/game/{game_id}", "/api/game"), None);
+        assert_eq!(matches("/api/game/{game_id}", "/api/game/1/ready"), None);
+        assert_eq!(matches("/api/game/{game_id}", "/api/other/1"), None);
+        assert_eq!(matches("/", "/"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn match_segments_rest_captures_the_remaining_path() {
+        assert_eq!(matches("/static/{*path}", "/static/js/app.js"), Some(pairs(&[("path", "js/app.js")])));
+        assert_eq!(matches("/static/{*path}", "/static"), Some(pairs(&[("path", "")])));
+        assert_eq!(matches("/static/{*path}", "/other/app.js"), None);
+    }
+}
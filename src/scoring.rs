@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{PlayerRoundResult, RoundResult};
+
+// 反应时间分档积分：反应时间小于 under_ms 得 points 分
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TimeTier {
+    pub under_ms: f64,
+    pub points: i32,
+}
+
+// 最快反应奖励：本轮第1名且反应时间小于 under_ms
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FastestBonus {
+    pub under_ms: f64,
+    pub points: i32,
+}
+
+// 连续奖励：连续 rounds 轮进入前 top_n 名，每满 rounds 轮奖励一次
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StreakBonus {
+    pub top_n: u8,
+    pub rounds: u8,
+    pub points: i32,
+}
+
+// 赛车游戏积分规则
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScoringRules {
+    #[serde(default)]
+    pub time_tiers: Vec<TimeTier>,   // 按顺序匹配的反应时间分档
+    #[serde(default)]
+    pub slowest_tier_points: i32,    // 慢于所有分档时的积分
+    #[serde(default)]
+    pub rank_points: Vec<i32>,       // 第1名、第2名……的名次积分
+    #[serde(default)]
+    pub participation_points: i32,   // 名次超出 rank_points 时的保底积分
+    #[serde(default)]
+    pub fastest_bonus: Option<FastestBonus>,
+    #[serde(default)]
+    pub false_start_points: i32,     // 抢跑得分（通常为负），且不再获得本轮其他积分
+    #[serde(default)]
+    pub streak_bonus: Option<StreakBonus>,
+}
+
+// 内置规则预设
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoringPreset {
+    Classic, // 原有规则：反应时间分档 + 名次奖励
+    Spec,    // 游戏文档中的规则
+}
+
+// 创建游戏时可选择预设名称，或直接给出完整规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScoringConfig {
+    Preset(ScoringPreset),
+    Custom(ScoringRules),
+}
+
+impl ScoringConfig {
+    pub fn into_rules(self) -> ScoringRules {
+        match self {
+            ScoringConfig::Preset(preset) => ScoringRules::preset(preset),
+            ScoringConfig::Custom(rules) => rules,
+        }
+    }
+}
+
+impl Default for ScoringRules {
+    fn default() -> Self {
+        Self::preset(ScoringPreset::Classic)
+    }
+}
+
+impl ScoringRules {
+    pub fn preset(preset: ScoringPreset) -> Self {
+        match preset {
+            ScoringPreset::Classic => Self {
+                time_tiers: vec![
+                    TimeTier { under_ms: 200.0, points: 15 }, // 极快反应
+                    TimeTier { under_ms: 300.0, points: 12 }, // 快速反应
+                    TimeTier { under_ms: 400.0, points: 10 }, // 正常反应
+                    TimeTier { under_ms: 500.0, points: 8 },  // 较慢反应
+                ],
+                slowest_tier_points: 5,
+                rank_points: vec![10, 7, 5],
+                participation_points: 3,
+                fastest_bonus: None,
+                false_start_points: -5,
+                streak_bonus: None,
+            },
+            ScoringPreset::Spec => Self {
+                time_tiers: Vec::new(),
+                slowest_tier_points: 0,
+                rank_points: vec![10, 7, 5, 3],
+                participation_points: 1,
+                fastest_bonus: Some(FastestBonus { under_ms: 200.0, points: 5 }),
+                false_start_points: -5,
+                streak_bonus: Some(StreakBonus { top_n: 2, rounds: 3, points: 10 }),
+            },
+        }
+    }
+
+    fn time_points(&self, reaction_time: f64) -> i32 {
+        self.time_tiers
+            .iter()
+            .find(|tier| reaction_time < tier.under_ms)
+            .map(|tier| tier.points)
+            .unwrap_or(self.slowest_tier_points)
+    }
+
+    fn rank_points(&self, rank: u8) -> i32 {
+        self.rank_points
+            .get(rank as usize - 1)
+            .copied()
+            .unwrap_or(self.participation_points)
+    }
+
+    // 玩家在此前各轮中连续进入前 top_n 名的轮数
+    fn previous_streak(player_id: u8, top_n: u8, history: &[RoundResult]) -> usize {
+        history
+            .iter()
+            .rev()
+            .take_while(|round| {
+                round
+                    .player_results
+                    .iter()
+                    .any(|r| r.player_id == player_id && r.rank.is_some_and(|rank| rank <= top_n))
+            })
+            .count()
+    }
+
+    // 为本轮结果计算排名和积分，history 为此前各轮的结果
+    pub fn score_round(&self, results: &mut [PlayerRoundResult], history: &[RoundResult]) {
+        for result in results.iter_mut() {
+            result.rank = None;
+            result.points = if result.is_false_start {
+                self.false_start_points
+            } else {
+                result.reaction_time.map(|time| self.time_points(time)).unwrap_or(0)
+            };
+        }
+
+        // 计算排名 - 只对有效反应的玩家排序
+        let mut valid_results: Vec<&mut PlayerRoundResult> = results
+            .iter_mut()
+            .filter(|r| !r.is_false_start && r.reaction_time.is_some())
+            .collect();
+        valid_results.sort_by(|a, b| a.reaction_time.unwrap_or_default().total_cmp(&b.reaction_time.unwrap_or_default()));
+
+        for (index, result) in valid_results.iter_mut().enumerate() {
+            let rank = (index + 1) as u8;
+            result.rank = Some(rank);
+            result.points += self.rank_points(rank);
+
+            if let Some(bonus) = &self.fastest_bonus {
+                if rank == 1 && result.reaction_time.is_some_and(|time| time < bonus.under_ms) {
+                    result.points += bonus.points;
+                }
+            }
+
+            if let Some(bonus) = &self.streak_bonus {
+                if bonus.rounds > 0 && rank <= bonus.top_n {
+                    let streak = Self::previous_streak(result.player_id, bonus.top_n, history) + 1;
+                    if streak % bonus.rounds as usize == 0 {
+                        result.points += bonus.points;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reaction(player_id: u8, reaction_time: f64) -> PlayerRoundResult {
+        PlayerRoundResult {
+            player_id,
+            reaction_time: Some(reaction_time),
+            is_false_start: false,
+            rank: None,
+            points: 0,
+        }
+    }
+
+    fn false_start(player_id: u8) -> PlayerRoundResult {
+        PlayerRoundResult {
+            player_id,
+            reaction_time: None,
+            is_false_start: true,
+            rank: None,
+            points: 0,
+        }
+    }
+
+    // 依次计分多轮，返回完整的历史
+    fn play(rules: &ScoringRules, rounds: Vec<Vec<PlayerRoundResult>>) -> Vec<RoundResult> {
+        let mut history = Vec::new();
+        for (index, mut results) in rounds.into_iter().enumerate() {
+            rules.score_round(&mut results, &history);
+            history.push(RoundResult {
+                round: index as u8 + 1,
+                player_results: results,
+            });
+        }
+        history
+    }
+
+    fn ranks_and_points(round: &RoundResult) -> Vec<(u8, Option<u8>, i32)> {
+        round.player_results.iter().map(|r| (r.player_id, r.rank, r.points)).collect()
+    }
+
+    #[test]
+    fn classic_rules_combine_time_tiers_and_ranks() {
+        let rules = ScoringRules::preset(ScoringPreset::Classic);
+        let mut no_reaction = reaction(5, 0.0);
+        no_reaction.reaction_time = None;
+        let history = play(
+            &rules,
+            vec![vec![reaction(1, 350.0), reaction(2, 150.0), reaction(3, 600.0), false_start(4), no_reaction]],
+        );
+        assert_eq!(
+            ranks_and_points(&history[0]),
+            vec![
+                (1, Some(2), 10 + 7),
+                (2, Some(1), 15 + 10),
+                (3, Some(3), 5 + 5),
+                (4, None, -5),
+                (5, None, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn ranks_beyond_rank_points_get_participation_points() {
+        let rules = ScoringRules {
+            rank_points: vec![10],
+            participation_points: 2,
+            time_tiers: Vec::new(),
+            ..ScoringRules::preset(ScoringPreset::Classic)
+        };
+        let history = play(&rules, vec![vec![reaction(1, 300.0), reaction(2, 400.0)]]);
+        assert_eq!(ranks_and_points(&history[0]), vec![(1, Some(1), 5 + 10), (2, Some(2), 5 + 2)]);
+    }
+
+    #[test]
+    fn fastest_bonus_only_for_first_place_under_threshold() {
+        let rules = ScoringRules::preset(ScoringPreset::Spec);
+        let history = play(
+            &rules,
+            vec![
+                vec![reaction(1, 180.0), reaction(2, 190.0)],
+                vec![reaction(1, 210.0), reaction(2, 250.0)],
+            ],
+        );
+        assert_eq!(ranks_and_points(&history[0]), vec![(1, Some(1), 10 + 5), (2, Some(2), 7)]);
+        assert_eq!(ranks_and_points(&history[1]), vec![(1, Some(1), 10), (2, Some(2), 7)]);
+    }
+
+    #[test]
+    fn streak_bonus_every_full_streak_of_top_finishes() {
+        let rules = ScoringRules::preset(ScoringPreset::Spec);
+        let round = || vec![reaction(1, 250.0), reaction(2, 300.0), reaction(3, 350.0)];
+        let history = play(&rules, (0..6).map(|_| round()).collect());
+
+        let points = |player_id: u8| -> Vec<i32> {
+            history
+                .iter()
+                .map(|round| round.player_results.iter().find(|r| r.player_id == player_id).unwrap().points)
+                .collect()
+        };
+        // 前两名每满3轮奖励一次，第3名不在 top_n 内
+        assert_eq!(points(1), vec![10, 10, 20, 10, 10, 20]);
+        assert_eq!(points(2), vec![7, 7, 17, 7, 7, 17]);
+        assert_eq!(points(3), vec![5; 6]);
+    }
+
+    #[test]
+    fn false_start_breaks_the_streak() {
+        let rules = ScoringRules::preset(ScoringPreset::Spec);
+        let history = play(
+            &rules,
+            vec![
+                vec![reaction(1, 250.0), reaction(2, 300.0)],
+                vec![reaction(1, 250.0), reaction(2, 300.0)],
+                vec![false_start(1), reaction(2, 300.0)],
+                vec![reaction(1, 250.0), reaction(2, 300.0)],
+                vec![reaction(1, 250.0), reaction(2, 300.0)],
+            ],
+        );
+        let player_one: Vec<i32> = history.iter().map(|round| round.player_results[0].points).collect();
+        assert_eq!(player_one, vec![10, 10, -5, 10, 10]);
+        // 玩家2连续5轮进入前两名，第3轮获得奖励
+        let player_two: Vec<i32> = history.iter().map(|round| round.player_results[1].points).collect();
+        assert_eq!(player_two, vec![7, 7, 10 + 10, 7, 7]);
+    }
+}
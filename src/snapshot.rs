@@ -0,0 +1,245 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::RacingGame;
+
+// 快照格式变化时递增，旧版本的快照不再加载
+const SNAPSHOT_VERSION: u32 = 1;
+
+// 赛车游戏快照：定期及关闭时写入磁盘，启动时恢复
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    saved_at: DateTime<Utc>,
+    games: Vec<RacingGame>,
+}
+
+// 先写临时文件并刷到磁盘再改名，写到一半崩溃或断电也不会破坏上一次的快照
+pub fn save(path: &Path, games: Vec<RacingGame>) -> std::io::Result<()> {
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        saved_at: Utc::now(),
+        games,
+    };
+    let content = serde_json::to_vec(&snapshot)?;
+    let temp = path.with_extension("tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(&content)?;
+    // 不先同步的话，断电后可能得到改名已生效但内容不完整的快照
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temp, path)?;
+    // 同步所在目录，让改名本身也落盘
+    #[cfg(unix)]
+    {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// 快照不存在时返回空列表
+pub fn load(path: &Path) -> std::io::Result<Vec<RacingGame>> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let snapshot: Snapshot = serde_json::from_slice(&content)?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(std::io::Error::other(format!("不支持的快照版本 {}", snapshot.version)));
+    }
+    println!("💾 读取 {} 保存的快照", snapshot.saved_at.to_rfc3339());
+    Ok(snapshot.games)
+}
+
+// 把读不出来的快照改名保留（如 racing_games.json.corrupt-20240101120000），
+// 避免被之后的快照覆盖，方便事后排查或手动恢复
+pub fn set_aside(path: &Path) -> std::io::Result<PathBuf> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".corrupt-{}", Utc::now().format("%Y%m%d%H%M%S")));
+    let target = PathBuf::from(name);
+    std::fs::rename(path, &target)?;
+    Ok(target)
+}
+
+// Instant 只在本进程内有意义，存盘时换算成 Unix 毫秒时间戳
+fn to_unix_ms(instant: Instant) -> f64 {
+    let (now, wall_now) = (Instant::now(), SystemTime::now());
+    let wall = if instant <= now {
+        wall_now - now.duration_since(instant)
+    } else {
+        wall_now + instant.duration_since(now)
+    };
+    wall.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64() * 1000.0).unwrap_or_default()
+}
+
+fn from_unix_ms(ms: f64) -> Instant {
+    let (now, wall_now) = (Instant::now(), SystemTime::now());
+    let wall = UNIX_EPOCH + Duration::from_secs_f64(ms.max(0.0) / 1000.0);
+    match wall_now.duration_since(wall) {
+        Ok(elapsed) => now.checked_sub(elapsed).unwrap_or(now),
+        Err(e) => now + e.duration(),
+    }
+}
+
+pub mod instant {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(instant: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
+        to_unix_ms(*instant).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Instant, D::Error> {
+        f64::deserialize(deserializer).map(from_unix_ms)
+    }
+}
+
+pub mod optional_instant {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(instant: &Option<Instant>, serializer: S) -> Result<S::Ok, S::Error> {
+        instant.map(to_unix_ms).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Instant>, D::Error> {
+        Ok(Option::<f64>::deserialize(deserializer)?.map(from_unix_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RaceTiming;
+    use crate::scoring::ScoringRules;
+    use crate::{ClockSample, GameState};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 测试用的快照路径，结束时删除所在目录
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "snapshot_test_{}_{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn millis_between(a: Instant, b: Instant) -> f64 {
+        if a >= b {
+            a.duration_since(b).as_secs_f64() * 1000.0
+        } else {
+            b.duration_since(a).as_secs_f64() * 1000.0
+        }
+    }
+
+    // 打完一回合后进入第二回合的比赛阶段，玩家1已经做出反应
+    fn racing_game() -> RacingGame {
+        let names = vec!["甲".to_string(), "乙".to_string()];
+        let mut game = RacingGame::new(2, 3, names, ScoringRules::default(), false, RaceTiming::default());
+        let synced_at = Instant::now();
+        let server_time = crate::server_clock_ms(synced_at);
+        let sample = ClockSample { client_send: server_time - 40.0, server_time, client_receive: server_time };
+        game.sync_clock(1, Some(&sample), synced_at).unwrap();
+
+        for round in 0..2 {
+            for id in 1..=2 {
+                game.set_ready(id, true).unwrap();
+            }
+            game.start_round().unwrap();
+            let later = Instant::now() + Duration::from_secs(60);
+            game.advance_lights(later);
+            game.record_reaction(1, None, later).unwrap();
+            if round == 0 {
+                game.finish_round().unwrap();
+            }
+        }
+        assert_eq!(game.game_state, GameState::Racing);
+        game
+    }
+
+    #[test]
+    fn instants_survive_the_unix_ms_round_trip() {
+        let now = Instant::now();
+        for instant in [now, now - Duration::from_millis(1234), now + Duration::from_secs(5)] {
+            let restored = from_unix_ms(to_unix_ms(instant));
+            assert!(millis_between(restored, instant) < 5.0);
+        }
+
+        let wall_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64() * 1000.0;
+        assert!((to_unix_ms(Instant::now()) - wall_ms).abs() < 5.0);
+    }
+
+    #[test]
+    fn racing_games_are_saved_loaded_and_rolled_back() {
+        let dir = TempDir::new();
+        let path = dir.0.join("racing_games.json");
+        let game = racing_game();
+        save(&path, vec![game.clone()]).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        let mut loaded = load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let mut restored = loaded.remove(0);
+        assert_eq!(restored.game_id, game.game_id);
+        assert_eq!(restored.game_state, GameState::Racing);
+        assert_eq!(restored.player_reactions, game.player_reactions);
+        assert_eq!(restored.reacted_players, vec![1]);
+        assert_eq!(restored.clock_offsets, game.clock_offsets);
+        assert_eq!(restored.clock_round_trips, game.clock_round_trips);
+        assert!(millis_between(restored.green_light_time.unwrap(), game.green_light_time.unwrap()) < 5.0);
+        assert!(millis_between(restored.last_activity, game.last_activity) < 5.0);
+
+        // 进行中的回合无法继续：回到等待状态，已完成回合的成绩保留
+        restored.rollback_interrupted_round();
+        assert_eq!(restored.game_state, GameState::Waiting);
+        assert_eq!(restored.current_round, 1);
+        assert_eq!(restored.round_results.len(), 1);
+        assert_eq!(
+            restored.players.iter().map(|p| p.score).collect::<Vec<_>>(),
+            game.players.iter().map(|p| p.score).collect::<Vec<_>>()
+        );
+        assert!(restored.players.iter().all(|p| !p.is_ready));
+        assert!(restored.player_reactions.is_empty() && restored.reacted_players.is_empty());
+        assert_eq!((restored.red_lights, restored.next_light_at, restored.green_light_time), (0, None, None));
+        assert!(restored.clock_offsets.is_empty() && restored.clock_round_trips.is_empty());
+
+        // 回滚后可以重新开始被打断的回合
+        for id in 1..=2 {
+            restored.set_ready(id, true).unwrap();
+        }
+        restored.start_round().unwrap();
+        assert_eq!(restored.game_state, GameState::Countdown);
+    }
+
+    #[test]
+    fn missing_files_are_empty_and_other_versions_are_refused() {
+        let dir = TempDir::new();
+        let path = dir.0.join("racing_games.json");
+        assert!(load(&path).unwrap().is_empty());
+
+        std::fs::write(&path, format!(r#"{{"version": {}, "saved_at": "2024-01-01T00:00:00Z", "games": []}}"#, SNAPSHOT_VERSION + 1))
+            .unwrap();
+        assert!(load(&path).is_err());
+
+        let kept = set_aside(&path).unwrap();
+        assert!(!path.exists() && kept.exists());
+        assert!(kept.to_string_lossy().contains("racing_games.json.corrupt-"));
+    }
+}
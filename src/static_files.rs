@@ -0,0 +1,485 @@
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Response, StatusCode};
+
+use crate::error::AppError;
+use crate::lock_or_recover;
+use crate::router::{with_header, HandlerResult, RouteContext};
+
+// 小于该大小的文件压缩收益不大，直接发送
+const MIN_COMPRESS_SIZE: usize = 1024;
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut output = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 5, 22);
+                    writer.write_all(data)?;
+                }
+                Ok(output)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    // 按 Accept-Encoding 选择编码，优先 brotli；明确列出的编码以它的 q 值为准，没列出时看 * 的 q 值
+    fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let weights: Vec<(&str, f32)> = accept_encoding
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let name = parts.next()?.trim();
+                let q = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!name.is_empty()).then_some((name, q))
+            })
+            .collect();
+        let weight = |name: &str| weights.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, q)| q);
+        [Encoding::Brotli, Encoding::Gzip]
+            .into_iter()
+            .find(|encoding| weight(encoding.name()).or_else(|| weight("*")).is_some_and(|q| q > 0.0))
+    }
+}
+
+// 按扩展名判断 Content-Type
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "eot" => "application/vnd.ms-fontobject",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+// 文本类资源才值得压缩，图片和字体本身已经压缩过
+fn is_compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/javascript")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("application/xml")
+        || content_type.starts_with("image/svg+xml")
+        || content_type.starts_with("application/wasm")
+}
+
+// 压缩结果缓存，按文件路径和编码区分，文件变化（ETag 不同）时重新压缩
+type CompressedCache = HashMap<(PathBuf, Encoding), (String, Arc<Vec<u8>>)>;
+
+#[cfg(feature = "embed-static")]
+static EMBEDDED: include_dir::Dir<'static> = include_dir::include_dir!("$CARGO_MANIFEST_DIR/static");
+
+// 静态资源来源：磁盘目录（开发模式，修改后立即生效）或编译进程序的资源
+enum Source {
+    Disk(PathBuf),
+    #[cfg(feature = "embed-static")]
+    Embedded(&'static include_dir::Dir<'static>),
+}
+
+// 找到的资源文件；path 在磁盘模式下是完整路径，内置模式下是相对路径
+struct Asset {
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+}
+
+// 只提供静态目录内文件的服务
+pub struct StaticFiles {
+    source: Source,
+    compressed: Mutex<CompressedCache>,
+}
+
+impl StaticFiles {
+    // 配置了静态目录（STATIC_DIR）时总是从该目录读取（开发模式）；
+    // 否则启用 embed-static 特性时使用内置资源，未启用时读取磁盘上的 static/ 目录
+    pub fn new(static_dir: Option<&Path>) -> Self {
+        if let Some(root) = static_dir {
+            return Self::from_disk(root);
+        }
+
+        #[cfg(feature = "embed-static")]
+        return Self::embedded();
+
+        #[cfg(not(feature = "embed-static"))]
+        {
+            // 不在项目目录下启动时，退回到编译时的项目 static/ 目录
+            let local = PathBuf::from("static");
+            let root = if local.is_dir() {
+                local
+            } else {
+                Path::new(env!("CARGO_MANIFEST_DIR")).join("static")
+            };
+            Self::from_disk(root)
+        }
+    }
+
+    pub fn from_disk(root: impl Into<PathBuf>) -> Self {
+        Self::with_source(Source::Disk(root.into()))
+    }
+
+    #[cfg(feature = "embed-static")]
+    pub fn embedded() -> Self {
+        Self::with_source(Source::Embedded(&EMBEDDED))
+    }
+
+    fn with_source(source: Source) -> Self {
+        Self {
+            source,
+            compressed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.source {
+            Source::Disk(root) => format!("磁盘目录 {}", root.display()),
+            #[cfg(feature = "embed-static")]
+            Source::Embedded(_) => "内置资源".to_string(),
+        }
+    }
+
+    fn resolve(&self, request_path: &str) -> Option<Asset> {
+        match &self.source {
+            Source::Disk(root) => resolve_disk(root, request_path),
+            #[cfg(feature = "embed-static")]
+            Source::Embedded(dir) => resolve_embedded(dir, request_path),
+        }
+    }
+
+    fn read(&self, asset: &Asset) -> Option<Vec<u8>> {
+        match &self.source {
+            Source::Disk(_) => std::fs::read(&asset.path).ok(),
+            #[cfg(feature = "embed-static")]
+            Source::Embedded(dir) => dir.get_file(&asset.path).map(|file| file.contents().to_vec()),
+        }
+    }
+
+    pub fn serve(&self, ctx: &RouteContext, request_path: &str) -> HandlerResult {
+        let not_found = || AppError::NotFound(format!("/{}", request_path.trim_start_matches('/')));
+        let asset = self.resolve(request_path).ok_or_else(not_found)?;
+
+        let modified_nanos = asset.modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        let last_modified = DateTime::<Utc>::from(asset.modified).format(HTTP_DATE_FORMAT).to_string();
+        let content_type = content_type(&asset.path);
+
+        let encoding = if is_compressible(content_type) && asset.len as usize >= MIN_COMPRESS_SIZE {
+            ctx.header("Accept-Encoding").and_then(|value| Encoding::negotiate(&value))
+        } else {
+            None
+        };
+        // 不同编码是不同的表示，ETag 需要区分
+        let etag = match encoding {
+            Some(encoding) => format!("\"{:x}-{:x}-{}\"", asset.len, modified_nanos, encoding.name()),
+            None => format!("\"{:x}-{:x}\"", asset.len, modified_nanos),
+        };
+
+        if not_modified(ctx, &etag, asset.modified) {
+            let response = Response::from_data(Vec::new()).with_status_code(StatusCode::from(304));
+            return Ok(cache_headers(response, &etag, &last_modified));
+        }
+
+        let body = match encoding {
+            Some(encoding) => self.compressed_body(&asset, encoding, &etag)?.to_vec(),
+            None => self.read(&asset).ok_or_else(not_found)?,
+        };
+
+        let mut response = with_header(Response::from_data(body), "Content-Type", content_type);
+        if let Some(encoding) = encoding {
+            response = with_header(response, "Content-Encoding", encoding.name());
+        }
+        Ok(cache_headers(response, &etag, &last_modified))
+    }
+
+    fn compressed_body(&self, asset: &Asset, encoding: Encoding, etag: &str) -> Result<Arc<Vec<u8>>, AppError> {
+        let key = (asset.path.clone(), encoding);
+        if let Some((cached_etag, body)) = lock_or_recover(&self.compressed).get(&key) {
+            if cached_etag == etag {
+                return Ok(Arc::clone(body));
+            }
+        }
+
+        let data = self
+            .read(asset)
+            .ok_or_else(|| AppError::Internal(format!("读取 {} 失败", asset.path.display())))?;
+        let body = Arc::new(encoding.compress(&data).map_err(|e| AppError::Internal(e.to_string()))?);
+        lock_or_recover(&self.compressed).insert(key, (etag.to_string(), Arc::clone(&body)));
+        Ok(body)
+    }
+}
+
+impl Default for StaticFiles {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+// 规范化路径后必须仍在静态目录内，防止 ../ 等方式访问目录外的文件
+fn resolve_disk(root: &Path, request_path: &str) -> Option<Asset> {
+    let root = root.canonicalize().ok()?;
+    let mut path = root.join(request_path.trim_start_matches('/')).canonicalize().ok()?;
+    if !path.starts_with(&root) {
+        return None;
+    }
+    if path.is_dir() {
+        path = path.join("index.html");
+    }
+    let metadata = std::fs::metadata(&path).ok().filter(|metadata| metadata.is_file())?;
+    Some(Asset {
+        path,
+        len: metadata.len(),
+        modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+    })
+}
+
+// 内置资源只按目录内的相对路径查找，含 .. 的路径直接拒绝
+#[cfg(feature = "embed-static")]
+fn resolve_embedded(dir: &'static include_dir::Dir<'static>, request_path: &str) -> Option<Asset> {
+    let segments: Vec<&str> = request_path.split('/').filter(|s| !s.is_empty() && *s != ".").collect();
+    if segments.contains(&"..") {
+        return None;
+    }
+    let mut path = PathBuf::from_iter(&segments);
+    if path.as_os_str().is_empty() || dir.get_dir(&path).is_some() {
+        path = path.join("index.html");
+    }
+    let file = dir.get_file(&path)?;
+    Some(Asset {
+        path,
+        len: file.contents().len() as u64,
+        modified: file.metadata().map(|metadata| metadata.modified()).unwrap_or(UNIX_EPOCH),
+    })
+}
+
+// If-None-Match 优先；没有时再看 If-Modified-Since（精确到秒）
+fn not_modified(ctx: &RouteContext, etag: &str, modified: SystemTime) -> bool {
+    if let Some(if_none_match) = ctx.header("If-None-Match") {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*");
+    }
+    ctx.header("If-Modified-Since")
+        .and_then(|since| DateTime::parse_from_rfc2822(&since).ok())
+        .is_some_and(|since| DateTime::<Utc>::from(modified).timestamp() <= since.timestamp())
+}
+
+fn cache_headers<R: std::io::Read>(response: Response<R>, etag: &str, last_modified: &str) -> Response<R> {
+    let response = with_header(response, "ETag", etag);
+    let response = with_header(response, "Last-Modified", last_modified);
+    // 每次使用前向服务器确认，文件未变化时只返回304
+    let response = with_header(response, "Cache-Control", "no-cache");
+    with_header(response, "Vary", "Accept-Encoding")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{HttpResponse, Router};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tiny_http::{Header, Method, TestRequest};
+
+    // 临时目录：root 是静态目录，它的上一级放一个不应被访问到的文件
+    struct TempSite {
+        base: PathBuf,
+        root: PathBuf,
+    }
+
+    impl TempSite {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let base = std::env::temp_dir().join(format!(
+                "static_files_test_{}_{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let root = base.join("site");
+            fs::create_dir_all(root.join("sub")).unwrap();
+            fs::write(base.join("secret.txt"), "secret").unwrap();
+            fs::write(root.join("index.html"), "<h1>首页</h1>").unwrap();
+            fs::write(root.join("sub/index.html"), "<h1>子目录</h1>").unwrap();
+            fs::write(root.join("app.js"), "console.log('赛车');\n".repeat(100)).unwrap();
+            Self { base, root }
+        }
+    }
+
+    impl Drop for TempSite {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn resolved(site: &TempSite, request_path: &str) -> Option<PathBuf> {
+        resolve_disk(&site.root, request_path).map(|asset| asset.path)
+    }
+
+    fn serve_file(ctx: &mut RouteContext, files: &StaticFiles) -> HandlerResult {
+        files.serve(ctx, ctx.param("path"))
+    }
+
+    fn get(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> HttpResponse {
+        let router = Router::new().route(Method::Get, "/{*path}", serve_file);
+        let mut request = TestRequest::new().with_path(path);
+        for (name, value) in headers {
+            request = request.with_header(Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap());
+        }
+        router.dispatch(&mut request.into(), files, std::time::Instant::now())
+    }
+
+    fn header(response: &HttpResponse, name: &str) -> Option<String> {
+        response
+            .headers()
+            .iter()
+            .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str().to_string())
+    }
+
+    #[test]
+    fn files_inside_the_root_resolve() {
+        let site = TempSite::new();
+        let root = site.root.canonicalize().unwrap();
+        assert_eq!(resolved(&site, "index.html"), Some(root.join("index.html")));
+        assert_eq!(resolved(&site, "/app.js"), Some(root.join("app.js")));
+        assert_eq!(resolved(&site, ""), Some(root.join("index.html")));
+        assert_eq!(resolved(&site, "sub"), Some(root.join("sub/index.html")));
+        assert_eq!(resolved(&site, "sub/../app.js"), Some(root.join("app.js")));
+        assert_eq!(resolved(&site, "missing.js"), None);
+    }
+
+    #[test]
+    fn paths_leaving_the_root_do_not_resolve() {
+        let site = TempSite::new();
+        let secret = site.base.join("secret.txt").canonicalize().unwrap();
+        assert_eq!(resolved(&site, "../secret.txt"), None);
+        assert_eq!(resolved(&site, "sub/../../secret.txt"), None);
+        assert_eq!(resolved(&site, "%2e%2e/secret.txt"), None);
+        assert_eq!(resolved(&site, ".."), None);
+        // 绝对路径也只在静态目录内查找
+        assert_eq!(resolved(&site, secret.to_str().unwrap()), None);
+        assert_eq!(resolved(&site, &format!("//{}", secret.display())), None);
+
+        // 经过路由解码的 %2e%2e 同样被拒绝
+        let files = StaticFiles::from_disk(&site.root);
+        for path in ["/%2e%2e/secret.txt", "/sub/%2E%2E/%2e%2e/secret.txt", "/..%2fsecret.txt"] {
+            assert_eq!(get(&files, path, &[]).status_code().0, 404, "{}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_pointing_outside_the_root_do_not_resolve() {
+        use std::os::unix::fs::symlink;
+        let site = TempSite::new();
+        symlink(site.base.join("secret.txt"), site.root.join("secret.txt")).unwrap();
+        symlink(&site.base, site.root.join("parent")).unwrap();
+        symlink(site.root.join("app.js"), site.root.join("alias.js")).unwrap();
+
+        assert_eq!(resolved(&site, "secret.txt"), None);
+        assert_eq!(resolved(&site, "parent/secret.txt"), None);
+        assert_eq!(resolved(&site, "parent/site/app.js"), Some(site.root.canonicalize().unwrap().join("app.js")));
+        assert_eq!(resolved(&site, "alias.js"), Some(site.root.canonicalize().unwrap().join("app.js")));
+    }
+
+    #[test]
+    fn matching_validators_return_304() {
+        let site = TempSite::new();
+        let files = StaticFiles::from_disk(&site.root);
+
+        let response = get(&files, "/app.js", &[("Accept-Encoding", "gzip")]);
+        assert_eq!(response.status_code().0, 200);
+        assert_eq!(header(&response, "Content-Encoding").as_deref(), Some("gzip"));
+        let etag = header(&response, "ETag").unwrap();
+        assert!(etag.ends_with("-gzip\""), "{}", etag);
+        let last_modified = header(&response, "Last-Modified").unwrap();
+
+        let gzip = ("Accept-Encoding", "gzip");
+        let response = get(&files, "/app.js", &[gzip, ("If-None-Match", &etag)]);
+        assert_eq!(response.status_code().0, 304);
+        assert_eq!(response.data_length(), Some(0));
+        assert_eq!(header(&response, "ETag").as_deref(), Some(etag.as_str()));
+
+        let weak = format!("\"other\", W/{}", etag);
+        assert_eq!(get(&files, "/app.js", &[gzip, ("If-None-Match", &weak)]).status_code().0, 304);
+        assert_eq!(get(&files, "/app.js", &[gzip, ("If-None-Match", "*")]).status_code().0, 304);
+        assert_eq!(get(&files, "/app.js", &[gzip, ("If-Modified-Since", &last_modified)]).status_code().0, 304);
+
+        // 不同编码的 ETag 不同，不能互相命中
+        assert_eq!(get(&files, "/app.js", &[("If-None-Match", &etag)]).status_code().0, 200);
+        assert_eq!(get(&files, "/app.js", &[gzip, ("If-None-Match", "\"other\"")]).status_code().0, 200);
+        let old = "Mon, 01 Jan 2001 00:00:00 GMT";
+        assert_eq!(get(&files, "/app.js", &[gzip, ("If-Modified-Since", old)]).status_code().0, 200);
+        // If-None-Match 优先于 If-Modified-Since
+        let both = [gzip, ("If-None-Match", "\"other\""), ("If-Modified-Since", last_modified.as_str())];
+        assert_eq!(get(&files, "/app.js", &both).status_code().0, 200);
+    }
+
+    #[test]
+    fn negotiate_respects_q_values_and_wildcards() {
+        assert_eq!(Encoding::negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate("GZIP"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("gzip; q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("gzip;q=0"), None);
+        assert_eq!(Encoding::negotiate("gzip;q=0.0, br;q=0"), None);
+        assert_eq!(Encoding::negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate("*;q=0"), None);
+        assert_eq!(Encoding::negotiate("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("gzip, *;q=0"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("identity"), None);
+        assert_eq!(Encoding::negotiate(""), None);
+    }
+}
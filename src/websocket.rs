@@ -0,0 +1,251 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Request, Response, StatusCode};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::error::AppError;
+use crate::events::RacingEvent;
+use crate::router::with_header;
+use crate::GameStateStorage;
+
+// 空闲时发送心跳，及时发现已断开的连接；超过两个周期没有收到任何帧则关闭连接
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+// 没有事件时检查客户端发来的帧（Close、Pong）的间隔
+const READ_POLL: Duration = Duration::from_millis(20);
+
+// 每个连接占用一个线程，同时保持的连接数由 racing.max_connections 限制
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+// 连接名额，连接线程结束时释放
+struct ConnectionSlot;
+
+impl ConnectionSlot {
+    fn acquire(max_connections: usize) -> Option<Self> {
+        CONNECTIONS
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| (count < max_connections).then_some(count + 1))
+            .ok()
+            .map(|_| ConnectionSlot)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn is_upgrade_request(request: &Request) -> bool {
+    request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Upgrade") && h.value.as_str().eq_ignore_ascii_case("websocket"))
+}
+
+// 处理 ws://host/racing/{game_id}：完成握手后由独立线程推送该游戏的事件
+pub fn handle_racing_socket(request: Request, game_id: &str, storage: &GameStateStorage) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| h.value.as_str().to_string());
+
+    let key = match key {
+        Some(key) => key,
+        None => {
+            let error = AppError::invalid_parameter("Sec-WebSocket-Key", "缺少 WebSocket 握手密钥");
+            let _ = request.respond(error.to_response());
+            return;
+        }
+    };
+
+    let max_connections = storage.config().max_connections;
+    let slot = match ConnectionSlot::acquire(max_connections) {
+        Some(slot) => slot,
+        None => {
+            let _ = request.respond(AppError::TooManyConnections(max_connections).to_response());
+            return;
+        }
+    };
+
+    // 先订阅再取快照，避免遗漏两者之间发生的事件；
+    // 游戏不存在或已过期时撤销订阅，否则这个游戏ID会一直留在订阅表中
+    let receiver = storage.events().subscribe(game_id);
+    let snapshot = match storage.get_game(game_id) {
+        Ok(game) => RacingEvent::StateChange { game },
+        Err(error) => {
+            storage.events().close(game_id);
+            let _ = request.respond(error.to_response());
+            return;
+        }
+    };
+    let snapshot = match serde_json::to_string(&snapshot) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            let _ = request.respond(AppError::Internal(e.to_string()).to_response());
+            return;
+        }
+    };
+
+    let response = Response::empty(StatusCode::from(101));
+    let response = with_header(response, "Upgrade", "websocket");
+    let response = with_header(response, "Connection", "Upgrade");
+    let response = with_header(response, "Sec-WebSocket-Accept", &derive_accept_key(key.as_bytes()));
+    let peer = request.remote_addr().copied();
+    let stream = request.upgrade("websocket", response);
+
+    let game_id = game_id.to_string();
+    thread::spawn(move || {
+        println!("🔌 WebSocket已连接: {}", game_id);
+        // 能拿到底层套接字时改为非阻塞读写，同时处理客户端发来的帧；否则只推送，靠心跳发现断线
+        match peer.and_then(duplicate_socket) {
+            Some(socket) => serve(WebSocket::from_raw_socket(socket, Role::Server, None), snapshot, &receiver, true),
+            None => serve(WebSocket::from_raw_socket(stream, Role::Server, None), snapshot, &receiver, false),
+        }
+        println!("🔌 WebSocket已断开: {}", game_id);
+        drop(slot);
+    });
+}
+
+// 推送快照和之后的事件，直到任一方关闭连接；readable 为 true 时底层套接字是非阻塞的
+fn serve<S: Read + Write>(mut socket: WebSocket<S>, snapshot: String, receiver: &Receiver<String>, readable: bool) {
+    let poll = if readable { READ_POLL } else { PING_INTERVAL };
+    let mut last_sent = Instant::now();
+    let mut last_heard = Instant::now();
+    let mut result = socket.send(Message::Text(snapshot));
+
+    while !is_fatal(&result) {
+        result = match receiver.recv_timeout(poll) {
+            Ok(message) => {
+                last_sent = Instant::now();
+                socket.send(Message::Text(message))
+            }
+            Err(RecvTimeoutError::Timeout) if last_sent.elapsed() >= PING_INTERVAL => {
+                last_sent = Instant::now();
+                socket.send(Message::Ping(Vec::new()))
+            }
+            // 非阻塞写入时可能还有没发完的数据
+            Err(RecvTimeoutError::Timeout) => socket.flush(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if !readable {
+            continue;
+        }
+
+        // 读完已到达的帧：Pong 等任何帧都说明客户端还在，收到 Close 后回复并结束
+        loop {
+            match socket.read() {
+                Ok(Message::Close(_)) => {
+                    let _ = socket.flush();
+                    return;
+                }
+                Ok(_) => last_heard = Instant::now(),
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => return,
+            }
+        }
+        if last_heard.elapsed() > PING_INTERVAL * 2 {
+            break;
+        }
+    }
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+// 非阻塞套接字暂时写不进去时数据留在发送缓冲区，下次再发
+fn is_fatal(result: &tungstenite::Result<()>) -> bool {
+    match result {
+        Ok(()) => false,
+        Err(tungstenite::Error::Io(e)) => e.kind() != ErrorKind::WouldBlock,
+        Err(_) => true,
+    }
+}
+
+// tiny_http 升级后只给出读写合一的流，无法设置超时也无法拆分读写。
+// 按对端地址在本进程打开的描述符中找到这个连接的套接字，复制一份设为非阻塞后独占使用
+#[cfg(unix)]
+fn duplicate_socket(peer: SocketAddr) -> Option<TcpStream> {
+    use std::os::fd::{BorrowedFd, RawFd};
+
+    let socket = std::fs::read_dir("/dev/fd")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<RawFd>().ok())
+        .find_map(|fd| {
+            // SAFETY: 描述符刚从 /dev/fd 列出，这里只用它复制出一个新的描述符；
+            // 即使它在此期间被关闭或被复用，复制失败或地址不匹配都会被丢弃
+            let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            let socket = TcpStream::from(borrowed.try_clone_to_owned().ok()?);
+            (socket.peer_addr().ok()? == peer).then_some(socket)
+        })?;
+    socket.set_nonblocking(true).ok()?;
+    Some(socket)
+}
+
+#[cfg(not(unix))]
+fn duplicate_socket(_peer: SocketAddr) -> Option<TcpStream> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RacingConfig;
+    use crate::database::SyncDatabaseManager;
+    use crate::CreateGameRequest;
+    use std::sync::Arc;
+    use tiny_http::Server;
+
+    fn wait_for_connections(count: usize) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if CONNECTIONS.load(Ordering::SeqCst) == count {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    // 连接线程在客户端发送 Close 或直接断开后很快结束，不用等下一次心跳
+    #[test]
+    fn closed_clients_release_their_connection_promptly() {
+        let config = RacingConfig { max_connections: 1, ..RacingConfig::default() };
+        let storage = GameStateStorage::with_database(Arc::new(SyncDatabaseManager::new()), config);
+        let game_id = storage.create_game(CreateGameRequest::default()).unwrap().game_id;
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/racing/{}", server.server_addr().to_ip().unwrap(), game_id);
+
+        let accept = |storage: &GameStateStorage| {
+            let request = server.recv().unwrap();
+            handle_racing_socket(request, &game_id, storage);
+        };
+
+        // 客户端主动关闭
+        let client = thread::spawn({
+            let url = url.clone();
+            move || {
+                let (mut socket, _) = tungstenite::connect(url).unwrap();
+                assert!(matches!(socket.read().unwrap(), Message::Text(_)));
+                socket.close(None).unwrap();
+                while socket.read().is_ok() {}
+            }
+        });
+        accept(&storage);
+        client.join().unwrap();
+        assert!(wait_for_connections(0));
+
+        // 客户端不发 Close 直接断开
+        let client = thread::spawn(move || {
+            let (mut socket, _) = tungstenite::connect(url).unwrap();
+            assert!(matches!(socket.read().unwrap(), Message::Text(_)));
+        });
+        accept(&storage);
+        client.join().unwrap();
+        assert!(wait_for_connections(0));
+    }
+}
@@ -8,6 +8,7 @@ class RacingGame {
         this.maxRounds = 5;
         this.countdownInterval = null;
         this.gameInterval = null;
+        this.socket = null;
         this.isGameActive = false;
         
         // 按键映射
@@ -87,6 +88,13 @@ class RacingGame {
             });
 
             const gameData = await response.json();
+            if (!response.ok) {
+                // 校验失败时列出每个出错字段的原因
+                const errors = gameData.details?.errors ?? [];
+                const reasons = errors.map(e => `${e.field}: ${e.message}`).join('\n');
+                alert(`创建游戏失败：${reasons || gameData.message}`);
+                return;
+            }
             this.gameId = gameData.game_id;
             this.gameState = gameData.game_state;
             this.players = gameData.players;
@@ -94,6 +102,9 @@ class RacingGame {
             
             this.showGameInterface();
             this.renderPlayers();
+            this.updateStatus('正在与服务器对时...', 'waiting');
+            await this.syncClock();
+            this.connectEvents();
             this.updateStatus('游戏已创建，等待开始...', 'waiting');
             
             document.getElementById('start-btn').style.display = 'inline-block';
@@ -104,6 +115,79 @@ class RacingGame {
         }
     }
 
+    // 与服务器对时，服务器据此校正按键时间戳
+    // 每次请求带上上一次的完整往返（发送时刻、服务器时刻、收到时刻），最后一次请求只用于上报
+    async syncClock(samples = 5) {
+        for (const player of this.players) {
+            let sample = null;
+            for (let i = 0; i <= samples; i++) {
+                try {
+                    const clientSend = Date.now();
+                    const response = await fetch('/api/racing/sync', {
+                        method: 'POST',
+                        headers: {
+                            'Content-Type': 'application/json',
+                        },
+                        body: JSON.stringify({
+                            game_id: this.gameId,
+                            player_id: player.id,
+                            sample
+                        })
+                    });
+                    const data = await response.json();
+                    sample = response.ok
+                        ? { client_send: clientSend, server_time: data.server_time, client_receive: Date.now() }
+                        : null;
+                } catch (error) {
+                    console.error('对时失败:', error);
+                    sample = null;
+                }
+            }
+        }
+    }
+
+    // 订阅服务器推送的游戏事件，连接不可用时退回轮询
+    connectEvents() {
+        if (!window.WebSocket) return;
+
+        const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+        const socket = new WebSocket(`${protocol}//${window.location.host}/racing/${this.gameId}`);
+
+        socket.onmessage = (event) => {
+            const data = JSON.parse(event.data);
+            switch (data.type) {
+                case 'light_change':
+                    if (this.isGameActive && this.gameState !== 'racing') {
+                        this.renderLights(data);
+                    }
+                    break;
+                case 'state_change':
+                    console.log('游戏状态变化:', data.game.game_state);
+                    break;
+                case 'player_result':
+                    console.log('玩家反应结果:', data.result);
+                    break;
+                case 'round_result':
+                    console.log('回合结果:', data.result);
+                    break;
+                case 'game_expired':
+                    // 长时间没有操作，服务器已清理本局游戏
+                    alert('游戏长时间没有操作，已过期，请重新创建');
+                    this.resetGame();
+                    break;
+            }
+        };
+        socket.onclose = () => {
+            if (this.socket === socket) this.socket = null;
+        };
+
+        this.socket = socket;
+    }
+
+    isSocketOpen() {
+        return this.socket && this.socket.readyState === WebSocket.OPEN;
+    }
+
     showGameInterface() {
         document.getElementById('game-setup').style.display = 'none';
         document.getElementById('game-interface').style.display = 'block';
@@ -144,18 +228,25 @@ class RacingGame {
     async startGame() {
         if (!this.gameId) return;
 
-        try {
-            const response = await fetch(`/api/racing/start/${this.gameId}`, {
-                method: 'POST'
-            });
-            
-            const gameData = await response.json();
-            this.updateGameState(gameData);
-            
-            this.startCountdown();
-            
-        } catch (error) {
-            console.error('开始游戏失败:', error);
+        this.startCountdown();
+    }
+
+    async markPlayersReady() {
+        for (const player of this.players) {
+            try {
+                await fetch('/api/racing/ready', {
+                    method: 'POST',
+                    headers: {
+                        'Content-Type': 'application/json',
+                    },
+                    body: JSON.stringify({
+                        game_id: this.gameId,
+                        player_id: player.id
+                    })
+                });
+            } catch (error) {
+                console.error('玩家准备失败:', error);
+            }
         }
     }
 
@@ -164,6 +255,9 @@ class RacingGame {
         this.updateStatus('红灯倒计时...', 'countdown');
         document.getElementById('start-btn').style.display = 'none';
 
+        // 同一键盘上的玩家一起开始，先为所有玩家发送准备
+        await this.markPlayersReady();
+
         // 关键：调用后端API重置游戏状态
         try {
             const response = await fetch(`/api/racing/start/${this.gameId}`, {
@@ -183,30 +277,43 @@ class RacingGame {
             return;
         }
 
-        let step = 0;
-        this.countdownInterval = setInterval(() => {
-            if (step < 5) {
-                document.getElementById(`light-${step}`).classList.add('active');
-                step++;
-            } else {
-                clearInterval(this.countdownInterval);
-                this.startRandomDelay();
+        // 信号灯由服务器调度，WebSocket推送不可用时轮询状态并同步显示
+        let polling = false;
+        this.countdownInterval = setInterval(async () => {
+            if (polling || !this.isGameActive || this.isSocketOpen()) return;
+            polling = true;
+            try {
+                const response = await fetch(`/api/racing/status/${this.gameId}`);
+                const gameData = await response.json();
+                this.renderLights(gameData);
+            } catch (error) {
+                console.error('获取信号灯状态失败:', error);
+            } finally {
+                polling = false;
             }
-        }, 1000);
+        }, 30);
     }
 
-    startRandomDelay() {
-        this.updateStatus('准备就绪，等待起跑信号...', 'ready');
-        
-        // 随机延迟1.5-5秒
-        const delay = Math.random() * 3500 + 1500;
-        
-        setTimeout(() => {
+    renderLights(gameData) {
+        if (gameData.game_state === 'racing') {
+            if (this.gameState === 'racing') return;
+            clearInterval(this.countdownInterval);
+            this.countdownInterval = null;
             this.startRacing();
-        }, delay);
+            return;
+        }
+
+        for (let i = 0; i < 5; i++) {
+            const light = document.getElementById(`light-${i}`);
+            if (light) light.classList.toggle('active', i < gameData.red_lights);
+        }
+
+        if (gameData.game_state === 'ready') {
+            this.updateStatus('准备就绪，等待起跑信号...', 'ready');
+        }
     }
 
-    async startRacing() {
+    startRacing() {
         if (!this.isGameActive) return;
 
         this.updateStatus('绿灯亮起！起跑！', 'racing');
@@ -218,15 +325,6 @@ class RacingGame {
             light.classList.add('green', 'active');
         }
 
-        // 通知后端绿灯已亮起
-        try {
-            await fetch(`/api/racing/trigger/${this.gameId}`, {
-                method: 'POST'
-            });
-        } catch (error) {
-            console.error('触发绿灯失败:', error);
-        }
-
         // 记录绿灯亮起时间
         this.greenLightTime = Date.now();
         this.gameState = 'racing';
@@ -254,11 +352,12 @@ class RacingGame {
         }
         this.reactedPlayers.add(playerId);
 
-        const reactionTime = Date.now() - this.greenLightTime;
-        this.recordReaction(playerId, reactionTime);
+        const pressedAt = Date.now();
+        const reactionTime = pressedAt - this.greenLightTime;
+        this.recordReaction(playerId, pressedAt, reactionTime);
     }
 
-    async recordReaction(playerId, reactionTime) {
+    async recordReaction(playerId, pressedAt, reactionTime) {
         try {
             console.log('准备发送反应数据:', {
                 game_id: this.gameId,
@@ -276,7 +375,7 @@ class RacingGame {
                 body: JSON.stringify({
                     game_id: this.gameId,
                     player_id: playerId,
-                    reaction_time: reactionTime
+                    client_timestamp: pressedAt
                 })
             });
             
@@ -291,14 +390,16 @@ class RacingGame {
             const result = await response.json();
             console.log('反应API成功响应:', result);
             
-            // 更新玩家显示
+            // 更新玩家显示（以服务器计算的反应时间为准）
             const player = this.players.find(p => p.id === playerId);
             if (player) {
-                document.getElementById(`reaction-${playerId}`).textContent = reactionTime.toFixed(0);
+                const serverTime = result.reaction_time;
+                document.getElementById(`reaction-${playerId}`).textContent =
+                    serverTime !== null ? serverTime.toFixed(0) : '-';
                 document.getElementById(`status-${playerId}`).textContent = 
-                    reactionTime < 0 ? '抢跑！' : `${reactionTime.toFixed(0)}ms`;
+                    result.is_false_start ? '抢跑！' : `${serverTime.toFixed(0)}ms`;
                 document.getElementById(`status-${playerId}`).className = 
-                    reactionTime < 0 ? 'false-start' : '';
+                    result.is_false_start ? 'false-start' : '';
             }
 
             // 检查是否所有玩家都已反应
@@ -558,6 +659,11 @@ class RacingGame {
             this.gameInterval = null;
         }
         
+        if (this.socket) {
+            this.socket.close();
+            this.socket = null;
+        }
+        
         this.isGameActive = false;
         this.gameId = null;
         this.players = [];